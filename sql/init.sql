@@ -1,6 +1,27 @@
 -- 如果存在minigame数据库，则不创建，反之则创建
 -- CREATE DATABASE IF NOT EXISTS minigame;
 
+-- 玩家 id 由服务器通过序列分配，客户端不能指定
+CREATE SEQUENCE IF NOT EXISTS player_id_seq;
+
+-- 创建玩家信息表（需先于 friend_mapping 创建，供外键引用）
+CREATE TABLE IF NOT EXISTS player_info (
+    player_id INT NOT NULL DEFAULT nextval('player_id_seq'),
+    player_name VARCHAR(255) NOT NULL,
+    PRIMARY KEY (player_id)
+);
+-- 兼容已存在的表：补上默认值，并让序列从现有最大 id 之后开始
+ALTER TABLE player_info ALTER COLUMN player_id SET DEFAULT nextval('player_id_seq');
+ALTER SEQUENCE player_id_seq OWNED BY player_info.player_id;
+SELECT setval('player_id_seq', COALESCE((SELECT MAX(player_id) FROM player_info), 0) + 1, false);
+-- 账号凭证：用户名 + argon2 密码哈希，管理员可重置他人密码
+ALTER TABLE player_info ADD COLUMN IF NOT EXISTS username VARCHAR(64) UNIQUE;
+ALTER TABLE player_info ADD COLUMN IF NOT EXISTS password_hash VARCHAR(255);
+ALTER TABLE player_info ADD COLUMN IF NOT EXISTS is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+-- 修改或重置密码时加一，使此前签发的 token 失效
+ALTER TABLE player_info ADD COLUMN IF NOT EXISTS token_version INT NOT NULL DEFAULT 0;
+-- 设置管理员：UPDATE player_info SET is_admin = TRUE WHERE username = '...';
+
 -- 创建好友关系的映射表，master_id 和 friend_id
 CREATE TABLE IF NOT EXISTS friend_mapping (
     master_id INT NOT NULL,
@@ -11,11 +32,10 @@ CREATE TABLE IF NOT EXISTS friend_mapping (
 ALTER TABLE friend_mapping ADD FOREIGN KEY (master_id) REFERENCES player_info(player_id);
 ALTER TABLE friend_mapping ADD FOREIGN KEY (friend_id) REFERENCES player_info(player_id);
 
-
-
--- 创建玩家信息表
-CREATE TABLE IF NOT EXISTS player_info (
-    player_id INT NOT NULL,
-    player_name VARCHAR(255) NOT NULL,
+-- 玩家段位分（Elo），游客不参与计分
+CREATE TABLE IF NOT EXISTS player_rating (
+    player_id INT NOT NULL REFERENCES player_info(player_id),
+    rating INT NOT NULL DEFAULT 1500,
+    games_played INT NOT NULL DEFAULT 0,
     PRIMARY KEY (player_id)
-);
\ No newline at end of file
+);
@@ -0,0 +1,97 @@
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::debug;
+
+use crate::{Room, RoomChannel};
+
+// 每个房间排队等待处理的命令上限
+const ROOM_COMMAND_BUFFER: usize = 64;
+
+// 发给房间任务的命令，在房间任务中按顺序执行；返回的回复在发布快照之后送出，
+// 调用方收到结果时快照已经包含这次修改
+type RoomCommand = Box<dyn FnOnce(&mut Room, &RoomChannel) -> Reply + Send>;
+type Reply = Box<dyn FnOnce() + Send>;
+
+/// 房间任务的句柄：房间任务独占 Room 和广播通道，是房间唯一的写者；
+/// HTTP 和 WebSocket 处理函数通过句柄发送命令、订阅广播、读取最近的快照
+#[derive(Clone)]
+pub struct RoomHandle {
+    room_id: String,
+    commands: mpsc::Sender<RoomCommand>,
+    channel: RoomChannel,
+    snapshot: watch::Receiver<Room>,
+}
+
+impl RoomHandle {
+    /// 启动房间任务；所有句柄被 drop 或房间关闭（Room::closed）后任务结束
+    pub fn spawn(room: Room, channel: RoomChannel) -> Self {
+        let room_id = room.room_id.clone();
+        let (commands, receiver) = mpsc::channel(ROOM_COMMAND_BUFFER);
+        let (snapshot_tx, snapshot) = watch::channel(room.clone());
+        tokio::spawn(run_room(room, channel.clone(), receiver, snapshot_tx));
+        Self {
+            room_id,
+            commands,
+            channel,
+            snapshot,
+        }
+    }
+
+    pub fn room_id(&self) -> &str {
+        &self.room_id
+    }
+
+    /// 在房间任务中读写房间并返回结果，同一房间的命令依次执行；房间任务已结束时返回 None
+    pub async fn update<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Room, &RoomChannel) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let command: RoomCommand = Box::new(move |room, channel| {
+            let value = f(room, channel);
+            Box::new(move || {
+                let _ = reply.send(value);
+            })
+        });
+        self.commands.send(command).await.ok()?;
+        result.await.ok()
+    }
+
+    /// 读取最近一次命令执行后的房间快照，不经过房间任务
+    pub fn read<R>(&self, f: impl FnOnce(&Room) -> R) -> R {
+        f(&self.snapshot.borrow())
+    }
+
+    pub fn snapshot(&self) -> Room {
+        self.snapshot.borrow().clone()
+    }
+
+    /// 房间广播通道，用于订阅和补发历史消息
+    pub fn channel(&self) -> &RoomChannel {
+        &self.channel
+    }
+
+    /// 两个句柄是否指向同一个房间任务
+    pub fn same_room(&self, other: &RoomHandle) -> bool {
+        self.commands.same_channel(&other.commands)
+    }
+}
+
+async fn run_room(
+    mut room: Room,
+    channel: RoomChannel,
+    mut commands: mpsc::Receiver<RoomCommand>,
+    snapshot: watch::Sender<Room>,
+) {
+    debug!("🏠 [room_actor] 房间任务启动 - room_id: {}", room.room_id);
+    while let Some(command) = commands.recv().await {
+        let reply = command(&mut room, &channel);
+        snapshot.send_replace(room.clone());
+        reply();
+        // 房间已销毁，之后的命令不再执行，调用方收到 None
+        if room.closed {
+            break;
+        }
+    }
+    debug!("🏠 [room_actor] 房间任务结束 - room_id: {}", room.room_id);
+}
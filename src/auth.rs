@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use axum::extract::{FromRequestParts, Query};
+use http::{StatusCode, request::Parts};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{AppState, KeyConfig};
+
+// token 载荷
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Claims {
+    // 玩家 id
+    pub sub: i32,
+    // 玩家名称
+    pub name: String,
+    // 是否为游客
+    #[serde(default)]
+    pub guest: bool,
+    // 签发时账号的 token_version，修改密码后旧 token 的版本不再匹配
+    #[serde(default)]
+    pub ver: i32,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+// 签发与校验 token 的密钥对
+pub struct JwtKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    validation: Validation,
+    ttl_secs: i64,
+    guest_ttl_secs: i64,
+}
+
+impl JwtKeys {
+    pub fn try_new(config: &KeyConfig) -> Result<Self> {
+        let (private_key, public_key) = config.load_pem()?;
+        // 早期 config.yaml 中提交过一对示例密钥，私钥已经公开，任何人都能用它签发 token
+        if is_sample_key(&public_key) {
+            anyhow::bail!("正在使用仓库中公开的示例密钥，请生成新的密钥对");
+        }
+        let encoding = EncodingKey::from_ed_pem(private_key.as_bytes())
+            .with_context(|| "解析私钥失败")?;
+        let decoding = DecodingKey::from_ed_pem(public_key.as_bytes())
+            .with_context(|| "解析公钥失败")?;
+        Ok(Self {
+            encoding,
+            decoding,
+            validation: Validation::new(Algorithm::EdDSA),
+            ttl_secs: config.token_ttl_secs,
+            guest_ttl_secs: config.guest_token_ttl_secs,
+        })
+    }
+
+    /// 为玩家签发 token，token_version 取自账号
+    pub fn sign(&self, player_id: i32, player_name: &str, token_version: i32) -> Result<String> {
+        self.issue(player_id, player_name, false, token_version, self.ttl_secs)
+    }
+
+    /// 为游客签发短期 token，返回 token 和过期时间
+    pub fn sign_guest(&self, player_id: i32, player_name: &str) -> Result<(String, i64)> {
+        let expires_at = chrono::Utc::now().timestamp() + self.guest_ttl_secs;
+        let token = self.issue(player_id, player_name, true, 0, self.guest_ttl_secs)?;
+        Ok((token, expires_at))
+    }
+
+    fn issue(
+        &self,
+        player_id: i32,
+        player_name: &str,
+        guest: bool,
+        token_version: i32,
+        ttl_secs: i64,
+    ) -> Result<String> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = Claims {
+            sub: player_id,
+            name: player_name.to_string(),
+            guest,
+            ver: token_version,
+            iat: now,
+            exp: now + ttl_secs,
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::EdDSA), &claims, &self.encoding)?;
+        Ok(token)
+    }
+
+    /// 校验 token 并返回载荷
+    pub fn verify(&self, token: &str) -> Result<Claims> {
+        let data = jsonwebtoken::decode::<Claims>(token, &self.decoding, &self.validation)?;
+        Ok(data.claims)
+    }
+}
+
+// 曾提交在 config.yaml 中的示例公钥（PEM 正文）
+const SAMPLE_PUBLIC_KEY: &str = "MCowBQYDK2VwAyEAUangbK8ZdOv5aISxPJuPvKwqUK38ZOHQ+1PdqF/N6E4=";
+
+fn is_sample_key(public_key: &str) -> bool {
+    let body: String = public_key
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .flat_map(|line| line.split_whitespace())
+        .collect();
+    body == SAMPLE_PUBLIC_KEY
+}
+
+// 已认证的玩家，从 Authorization 头（或 /ws 的 token 参数）中解析
+#[derive(Debug, Clone)]
+pub struct AuthPlayer {
+    pub player_id: i32,
+    pub player_name: String,
+    pub guest: bool,
+}
+
+impl FromRequestParts<AppState> for AuthPlayer {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = match bearer_token(parts) {
+            Some(token) => token,
+            None => return Err((StatusCode::UNAUTHORIZED, "缺少token")),
+        };
+        match state.jwt.verify(&token) {
+            // 游客记录已过期、服务器重启或已升级为正式账号时，旧的游客 token 不再可用
+            Ok(claims) if claims.guest && !state.guests.contains_key(&claims.sub) => {
+                error!("❌ [auth] 游客身份已失效 - player_id: {}", claims.sub);
+                Err((StatusCode::UNAUTHORIZED, "游客身份已失效，请重新登录"))
+            }
+            Ok(claims) if !claims.guest => {
+                // 修改或重置密码后 token_version 递增，此前签发的 token 立即失效
+                match state.storage.players.account(claims.sub).await {
+                    Ok(Some(account)) if account.token_version == claims.ver => Ok(AuthPlayer {
+                        player_id: claims.sub,
+                        player_name: claims.name,
+                        guest: false,
+                    }),
+                    Ok(_) => {
+                        error!("❌ [auth] token已被吊销 - player_id: {}", claims.sub);
+                        Err((StatusCode::UNAUTHORIZED, "token已失效，请重新登录"))
+                    }
+                    Err(e) => {
+                        error!("❌ [auth] 获取账号失败 - player_id: {}, 错误: {}", claims.sub, e);
+                        Err((StatusCode::SERVICE_UNAVAILABLE, "校验token失败，请稍后再试"))
+                    }
+                }
+            }
+            Ok(claims) => Ok(AuthPlayer {
+                player_id: claims.sub,
+                player_name: claims.name,
+                guest: true,
+            }),
+            Err(e) => {
+                error!("❌ [auth] token校验失败 - 错误: {}", e);
+                Err((StatusCode::UNAUTHORIZED, "token无效或已过期"))
+            }
+        }
+    }
+}
+
+// 浏览器的 WebSocket 无法设置请求头，因此同时支持 ?token= 查询参数
+fn bearer_token(parts: &Parts) -> Option<String> {
+    if let Some(value) = parts.headers.get(http::header::AUTHORIZATION) {
+        return value
+            .to_str()
+            .ok()
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim().to_string());
+    }
+    let Query(params) = Query::<HashMap<String, String>>::try_from_uri(&parts.uri).ok()?;
+    params.get("token").cloned()
+}
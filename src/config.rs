@@ -19,17 +19,255 @@ pub struct BindConfig {
     pub port: u16,
 }
 
+// 签名密钥配置（Ed25519 PEM），密钥不写在配置文件中
 #[derive(Debug, Deserialize, Serialize)]
 pub struct KeyConfig {
-    pub private_key: String,
-    pub public_key: String,
+    // 私钥 PEM 文件路径；设置了环境变量 MINIGAME_PRIVATE_KEY 时改用其中的 PEM 内容
+    #[serde(default = "default_private_key_file")]
+    pub private_key_file: String,
+    // 公钥 PEM 文件路径；设置了环境变量 MINIGAME_PUBLIC_KEY 时改用其中的 PEM 内容
+    #[serde(default = "default_public_key_file")]
+    pub public_key_file: String,
+    // token 有效期（秒）
+    #[serde(default = "default_token_ttl_secs")]
+    pub token_ttl_secs: i64,
+    // 游客 token 有效期（秒）
+    #[serde(default = "default_guest_token_ttl_secs")]
+    pub guest_token_ttl_secs: i64,
+}
+
+const PRIVATE_KEY_ENV: &str = "MINIGAME_PRIVATE_KEY";
+const PUBLIC_KEY_ENV: &str = "MINIGAME_PUBLIC_KEY";
+
+impl KeyConfig {
+    /// 读取私钥和公钥的 PEM 内容，环境变量优先于文件
+    pub fn load_pem(&self) -> Result<(String, String)> {
+        let private_key = read_pem(PRIVATE_KEY_ENV, &self.private_key_file)?;
+        let public_key = read_pem(PUBLIC_KEY_ENV, &self.public_key_file)?;
+        Ok((private_key, public_key))
+    }
+}
+
+fn read_pem(env: &str, path: &str) -> Result<String> {
+    if let Ok(pem) = std::env::var(env)
+        && !pem.trim().is_empty()
+    {
+        return Ok(pem);
+    }
+    std::fs::read_to_string(path)
+        .with_context(|| format!("无法读取密钥文件: {}（或设置环境变量 {}）", path, env))
+}
+
+fn default_private_key_file() -> String {
+    "keys/private.pem".to_string()
+}
+
+fn default_public_key_file() -> String {
+    "keys/public.pem".to_string()
+}
+
+fn default_token_ttl_secs() -> i64 {
+    7 * 24 * 3600
+}
+
+fn default_guest_token_ttl_secs() -> i64 {
+    2 * 3600
+}
+
+// 房间配置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoomConfig {
+    // 服务器允许的单个房间最大人数，创建房间时指定的人数不能超过该值
+    pub max_players: usize,
+    // 每辆车的座位数
+    pub car_seats: usize,
+    // 开始比赛前的倒计时（秒）
+    #[serde(default = "default_countdown_secs")]
+    pub countdown_secs: u64,
+    // 结果展示时间（秒），之后房间回到 Lobby
+    #[serde(default = "default_results_secs")]
+    pub results_secs: u64,
+    // 比赛最长时间（秒），到期仍没有提交成绩则中止比赛回到 Lobby
+    #[serde(default = "default_race_timeout_secs")]
+    pub race_timeout_secs: u64,
+    // 好友邀请有效期（秒）
+    #[serde(default = "default_invite_ttl_secs")]
+    pub invite_ttl_secs: i64,
+    // 玩家掉线后保留座位等待重连的时间（秒）
+    #[serde(default = "default_reconnect_grace_secs")]
+    pub reconnect_grace_secs: u64,
+    // 每个房间保留的历史消息条数，客户端落后时从中补发
+    #[serde(default = "default_history_len")]
+    pub history_len: usize,
+    // 同一玩家再次连接房间时的处理方式
+    #[serde(default)]
+    pub duplicate_connection: DuplicateConnectionPolicy,
+    // 创建后一直没有玩家加入的房间保留多久（秒），到期关闭
+    #[serde(default = "default_empty_room_ttl_secs")]
+    pub empty_room_ttl_secs: u64,
+}
+
+// 同一玩家已在房间中（其他标签页、掉线前重连）时再次连接 /ws 的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateConnectionPolicy {
+    // 新连接生效，旧连接离开原房间并以 4002 关闭
+    #[default]
+    NewestWins,
+    // 拒绝新连接，需先退出原房间或使用 resume_token 重连
+    RejectNew,
+}
+
+fn default_countdown_secs() -> u64 {
+    3
+}
+
+fn default_results_secs() -> u64 {
+    10
+}
+
+fn default_race_timeout_secs() -> u64 {
+    600
+}
+
+fn default_invite_ttl_secs() -> i64 {
+    120
+}
+
+fn default_reconnect_grace_secs() -> u64 {
+    30
+}
+
+fn default_history_len() -> usize {
+    200
+}
+
+fn default_empty_room_ttl_secs() -> u64 {
+    60
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        Self {
+            max_players: 8,
+            car_seats: 2,
+            countdown_secs: default_countdown_secs(),
+            results_secs: default_results_secs(),
+            race_timeout_secs: default_race_timeout_secs(),
+            invite_ttl_secs: default_invite_ttl_secs(),
+            reconnect_grace_secs: default_reconnect_grace_secs(),
+            history_len: default_history_len(),
+            duplicate_connection: DuplicateConnectionPolicy::default(),
+            empty_room_ttl_secs: default_empty_room_ttl_secs(),
+        }
+    }
+}
+
+// 可选的天气 / 背景目录，列表为空时不限制
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CatalogConfig {
+    #[serde(default)]
+    pub weather_ids: Vec<i32>,
+    #[serde(default)]
+    pub background_ids: Vec<i32>,
+}
+
+impl CatalogConfig {
+    pub fn has_weather(&self, weather_id: i32) -> bool {
+        self.weather_ids.is_empty() || self.weather_ids.contains(&weather_id)
+    }
+
+    pub fn has_background(&self, background_id: i32) -> bool {
+        self.background_ids.is_empty() || self.background_ids.contains(&background_id)
+    }
+}
+
+// 快速匹配配置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MatchConfig {
+    // 排队超时时间（秒）
+    pub timeout_secs: u64,
+    // 没有合适的房间时，凑够多少名玩家才新建房间
+    pub min_players: usize,
+    // 排队玩家都未指定天气 / 背景时新房间使用的默认值
+    pub default_weather_id: i32,
+    pub default_background_id: i32,
+    // 初始分数窗口：只匹配平均分与玩家分数相差不超过该值的房间
+    pub rating_window: i32,
+    // 每排队一秒窗口扩大的分数
+    pub rating_window_growth: i32,
+    pub max_rating_window: i32,
+}
+
+impl MatchConfig {
+    /// 排队一段时间后的分数窗口
+    pub fn rating_window(&self, waited: std::time::Duration) -> i32 {
+        let growth = self.rating_window_growth as i64 * waited.as_secs() as i64;
+        (self.rating_window as i64 + growth).min(self.max_rating_window as i64) as i32
+    }
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 60,
+            min_players: 2,
+            default_weather_id: 1,
+            default_background_id: 1,
+            rating_window: 100,
+            rating_window_growth: 10,
+            max_rating_window: 800,
+        }
+    }
+}
+
+// 游客配置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuestConfig {
+    // 同时存在的游客上限，达到上限后拒绝新的游客登录
+    pub max_guests: usize,
+    // 定期清理过期游客的间隔（秒）
+    pub purge_interval_secs: u64,
+}
+
+impl Default for GuestConfig {
+    fn default() -> Self {
+        Self {
+            max_guests: 10000,
+            purge_interval_secs: 300,
+        }
+    }
+}
+
+// 存储后端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    // 使用 database 连接串连接 Postgres
+    #[default]
+    Postgres,
+    // 数据只保存在内存中，进程退出后丢失；用于测试和本地演示，不需要数据库
+    Memory,
 }
 
 // 主配置结构体
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub bind: BindConfig,
+    // Postgres 连接串，storage 为 memory 时可省略
+    #[serde(default)]
     pub database: String,
+    #[serde(default)]
+    pub storage: StorageBackend,
+    pub key: KeyConfig,
+    #[serde(default)]
+    pub room: RoomConfig,
+    #[serde(default)]
+    pub matchmaking: MatchConfig,
+    #[serde(default)]
+    pub catalog: CatalogConfig,
+    #[serde(default)]
+    pub guest: GuestConfig,
 }
 
 impl Config {
@@ -0,0 +1,57 @@
+use axum::extract::ws::Message;
+use http::HeaderValue;
+
+use crate::{ClientMessage, FrameError, ServerMessage};
+
+// Sec-WebSocket-Protocol 中的子协议名称
+pub const JSON_SUBPROTOCOL: &str = "minigame.json";
+pub const MSGPACK_SUBPROTOCOL: &str = "minigame.msgpack";
+// 服务器支持的子协议，按优先级排列
+pub const SUPPORTED_SUBPROTOCOLS: [&str; 2] = [JSON_SUBPROTOCOL, MSGPACK_SUBPROTOCOL];
+
+// 连接的序列化格式，握手时确定，之后不再改变
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    // JSON 文本帧（默认）
+    Json,
+    // MessagePack 二进制帧，面向移动端
+    MessagePack,
+}
+
+impl WireFormat {
+    /// 根据握手时选中的子协议确定格式，未协商子协议时使用 JSON
+    pub fn from_subprotocol(protocol: Option<&HeaderValue>) -> Self {
+        match protocol.and_then(|p| p.to_str().ok()) {
+            Some(MSGPACK_SUBPROTOCOL) => WireFormat::MessagePack,
+            _ => WireFormat::Json,
+        }
+    }
+
+    /// 序列化服务器消息为 WebSocket 帧
+    pub fn encode(&self, message: &ServerMessage) -> Message {
+        // ServerMessage 只包含可序列化的字段，序列化不会失败
+        match self {
+            WireFormat::Json => {
+                Message::Text(serde_json::to_string(message).unwrap_or_default().into())
+            }
+            WireFormat::MessagePack => {
+                Message::Binary(rmp_serde::to_vec_named(message).unwrap_or_default().into())
+            }
+        }
+    }
+
+    /// 反序列化客户端帧，帧类型必须与连接的格式一致
+    pub fn decode(&self, message: &Message) -> Result<ClientMessage, FrameError> {
+        match (self, message) {
+            (WireFormat::Json, Message::Text(text)) => serde_json::from_str(text.as_str())
+                .map_err(|e| FrameError::Malformed(e.to_string())),
+            (WireFormat::MessagePack, Message::Binary(bytes)) => {
+                rmp_serde::from_slice(bytes).map_err(|e| FrameError::Malformed(e.to_string()))
+            }
+            (WireFormat::Json, _) => Err(FrameError::Malformed("JSON 连接只接受文本帧".to_string())),
+            (WireFormat::MessagePack, _) => Err(FrameError::Malformed(
+                "MessagePack 连接只接受二进制帧".to_string(),
+            )),
+        }
+    }
+}
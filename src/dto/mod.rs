@@ -1,4 +1,8 @@
 mod request;
 mod response;
+mod protocol;
+mod codec;
 pub use request::*;
-pub use response::*;
\ No newline at end of file
+pub use response::*;
+pub use protocol::*;
+pub use codec::*;
@@ -0,0 +1,234 @@
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    MessageResponse, MessageType, Room, RoomEvent, RoomListPage, RoomPhase, SUPPORTED_SUBPROTOCOLS,
+};
+
+// 当前 WebSocket 协议版本
+pub const PROTOCOL_VERSION: u32 = 1;
+// 服务器仍兼容的最低协议版本
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// 协商协议版本：未指定时使用当前版本，超出服务器版本时降级到当前版本，低于最低版本时返回 None
+pub fn negotiate_protocol_version(requested: Option<u32>) -> Option<u32> {
+    match requested {
+        None => Some(PROTOCOL_VERSION),
+        Some(version) if version < MIN_PROTOCOL_VERSION => None,
+        Some(version) => Some(version.min(PROTOCOL_VERSION)),
+    }
+}
+
+/// 客户端 -> 服务器
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// 聊天消息；player_id 可省略，若携带必须与连接身份一致
+    Text {
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        player_id: Option<i32>,
+    },
+    /// 表情消息
+    Emoji {
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        player_id: Option<i32>,
+    },
+    /// 请求补发序号大于 after_seq 的房间消息，缺口过大时服务器改为下发一次 sync
+    Replay { after_seq: u64 },
+}
+
+/// 服务器 -> 客户端
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// 连接建立后的第一帧，携带协商后的协议版本；resume_token 用于掉线后通过 /ws?resume_token= 接回座位；
+    /// seq 为 room_info 对应的房间消息序号，之后的房间消息从 seq + 1 开始；
+    /// 重连时 Welcome 之后会先补发掉线期间错过的 text / emoji（seq 不大于 Welcome 的 seq），
+    /// 房间状态以 room_info 为准，不再补发之前的 sync / host_changed / phase_changed
+    Welcome {
+        protocol_version: u32,
+        room_info: Room,
+        seq: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
+    /// 房间消息（text / emoji / sync / host_changed / phase_changed）都带有房间内递增的 seq，
+    /// 客户端发现序号不连续时可以发送 replay；补发与实时消息可能重复，按 seq 去重即可
+    Text {
+        seq: u64,
+        player_id: i32,
+        content: String,
+    },
+    Emoji {
+        seq: u64,
+        player_id: i32,
+        content: String,
+    },
+    /// 房间状态同步
+    Sync {
+        seq: u64,
+        room_info: Room,
+    },
+    /// 房主离开后房间移交给新房主
+    HostChanged {
+        seq: u64,
+        room_id: String,
+        owner_id: i32,
+    },
+    /// 房间阶段变化；deadline 为服务器自动推进到下一阶段的时间（Unix 毫秒）
+    PhaseChanged {
+        seq: u64,
+        room_id: String,
+        phase: RoomPhase,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        deadline: Option<i64>,
+    },
+    /// 好友邀请加入房间，推送到被邀请玩家打开的所有连接；expires_at 为 Unix 秒
+    RoomInvite {
+        invite_id: String,
+        room_id: String,
+        from_id: i32,
+        from_name: String,
+        expires_at: i64,
+    },
+    /// 被邀请的好友接受或拒绝了邀请，推送给邀请方
+    InviteAnswered {
+        invite_id: String,
+        player_id: i32,
+        accepted: bool,
+    },
+    /// 大厅房间列表，仅在 /ws/lobby 连接上下发，房间变化时推送最新一页
+    RoomList(RoomListPage),
+    /// 已进入快速匹配队列，仅在 /ws/match 连接上下发
+    MatchQueued {
+        timeout_secs: u64,
+    },
+    /// 匹配成功，客户端使用这些参数连接 /ws 加入房间
+    MatchFound {
+        room_id: String,
+        car_id: i32,
+        weather_id: i32,
+        background_id: i32,
+    },
+    /// 匹配结束且未分配房间
+    MatchCancelled {
+        reason: MatchCancelReason,
+    },
+    /// 客户端帧无法处理
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// 帧格式错误或类型未知
+    MalformedFrame,
+    /// 帧中的 player_id 与连接身份不一致
+    SenderMismatch,
+    /// 房主关闭了观战者聊天
+    SpectatorChatDisabled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchCancelReason {
+    /// 玩家主动取消，或重新排队替换了旧的排队
+    Cancelled,
+    /// 排队超时
+    Timeout,
+}
+
+impl ServerMessage {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// 房间消息的序号，其他消息返回 None
+    pub fn seq(&self) -> Option<u64> {
+        match self {
+            ServerMessage::Welcome { seq, .. }
+            | ServerMessage::Text { seq, .. }
+            | ServerMessage::Emoji { seq, .. }
+            | ServerMessage::Sync { seq, .. }
+            | ServerMessage::HostChanged { seq, .. }
+            | ServerMessage::PhaseChanged { seq, .. } => Some(*seq),
+            _ => None,
+        }
+    }
+
+    /// 将房间广播消息转换为下发给客户端的消息；Quit 只在服务器内部使用，返回 None
+    pub fn from_broadcast(event: &RoomEvent) -> Option<Self> {
+        let seq = event.seq;
+        match &event.message {
+            MessageType::Text(MessageResponse { player_id, content }) => Some(ServerMessage::Text {
+                seq,
+                player_id: *player_id,
+                content: content.clone(),
+            }),
+            MessageType::Emoji(MessageResponse { player_id, content }) => {
+                Some(ServerMessage::Emoji {
+                    seq,
+                    player_id: *player_id,
+                    content: content.clone(),
+                })
+            }
+            MessageType::Sync(room_info) => Some(ServerMessage::Sync {
+                seq,
+                room_info: room_info.as_ref().clone(),
+            }),
+            MessageType::HostChanged(room_id, owner_id) => Some(ServerMessage::HostChanged {
+                seq,
+                room_id: room_id.clone(),
+                owner_id: *owner_id,
+            }),
+            MessageType::PhaseChanged(room_id, phase, deadline) => Some(ServerMessage::PhaseChanged {
+                seq,
+                room_id: room_id.clone(),
+                phase: *phase,
+                deadline: *deadline,
+            }),
+            MessageType::Quit(..) => None,
+        }
+    }
+}
+
+// 客户端帧解析错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameError {
+    // 格式错误（无法反序列化、缺少字段、未知类型）
+    Malformed(String),
+    // 帧中声明的 player_id 与连接的认证身份不一致
+    SenderMismatch(i32),
+}
+
+impl FrameError {
+    /// 转换为下发给客户端的错误帧
+    pub fn to_server_message(&self) -> ServerMessage {
+        match self {
+            FrameError::Malformed(reason) => ServerMessage::error(ErrorCode::MalformedFrame, reason.clone()),
+            FrameError::SenderMismatch(_) => {
+                ServerMessage::error(ErrorCode::SenderMismatch, "player_id与连接身份不一致")
+            }
+        }
+    }
+}
+
+/// 生成协议的 JSON Schema 文档，供 Unity / Web 客户端同步
+pub fn protocol_schema() -> serde_json::Value {
+    json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "min_protocol_version": MIN_PROTOCOL_VERSION,
+        "subprotocols": SUPPORTED_SUBPROTOCOLS,
+        "client_message": schema_for!(ClientMessage),
+        "server_message": schema_for!(ServerMessage),
+    })
+}
@@ -1,19 +1,20 @@
 use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct  JoinRoomRequest {
-    pub room_id: i32,
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
     pub player_id: i32,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct  QuitRoomRequest {
-    pub room_id: i32,
-    pub player_id: i32,
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChangeCarRequest {
-    pub room_id: i32,
-    pub player_id: i32,
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
     pub car_id: i32,
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::Room;
+use crate::{QuitReason, Room, RoomPhase};
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MessageResponse {
     pub player_id : i32,
@@ -11,6 +12,53 @@ pub struct MessageResponse {
 pub enum MessageType {
     Text(MessageResponse),
     Emoji(MessageResponse),
-    Sync(Room),
-    Quit(i32,i32)
-}
\ No newline at end of file
+    Sync(Box<Room>),
+    // (player_id, room_id, 服务器主动断开的原因；掉线或连接自行关闭时为空)
+    Quit(i32, String, Option<QuitReason>),
+    // (room_id, 新房主 player_id)
+    HostChanged(String, i32),
+    // (room_id, 新阶段, 服务器自动推进的时间戳（毫秒）)
+    PhaseChanged(String, RoomPhase, Option<i64>),
+}
+// 大厅列表中的房间摘要
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct RoomSummary {
+    pub room_id: String,
+    pub player_count: usize,
+    pub max_players: usize,
+    pub weather_id: i32,
+    pub background_id: i32,
+    pub host_id: i32,
+    // 房主尚未连接到房间时为空
+    pub host_name: Option<String>,
+    pub phase: RoomPhase,
+}
+
+impl From<&Room> for RoomSummary {
+    fn from(room: &Room) -> Self {
+        Self {
+            room_id: room.room_id.clone(),
+            player_count: room.players.len(),
+            max_players: room.max_players,
+            weather_id: room.weather_id,
+            background_id: room.background_id,
+            host_id: room.owner_id,
+            host_name: room
+                .players
+                .iter()
+                .find(|p| p.player_id == room.owner_id)
+                .map(|p| p.player_name.clone()),
+            phase: room.phase,
+        }
+    }
+}
+
+// 分页后的房间列表
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct RoomListPage {
+    pub rooms: Vec<RoomSummary>,
+    // 满足筛选条件的房间总数
+    pub total: usize,
+    pub page: usize,
+    pub page_size: usize,
+}
@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::Json;
+use axum::response::IntoResponse;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::error;
+
+use crate::{Account, AppState, AuthPlayer, Friend, Guest};
+
+// 密码最短长度
+const MIN_PASSWORD_LEN: usize = 6;
+
+// player_id 由服务器分配，请求中携带 player_id 会被拒绝
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub player_name: String,
+    pub password: String,
+}
+pub async fn register(
+    State(state): State<AppState>,
+    Json(request): Json<RegisterRequest>,
+) -> impl IntoResponse {
+    if request.username.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "用户名不能为空").into_response();
+    }
+    if request.password.len() < MIN_PASSWORD_LEN {
+        return (StatusCode::BAD_REQUEST, "密码长度不足").into_response();
+    }
+    match Account::register(
+        &state.storage,
+        request.username.trim(),
+        &request.player_name,
+        &request.password,
+    )
+    .await
+    {
+        Ok(player_id) => {
+            let json = json!({
+                "player_id": player_id,
+                "content": "注册成功",
+            });
+            (StatusCode::OK, Json(json)).into_response()
+        }
+        Err(e) => {
+            error!("❌ [register] 注册失败 - 错误: {}", e);
+            (StatusCode::BAD_REQUEST, "注册失败").into_response()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+pub async fn login(
+    State(state): State<AppState>,
+    Json(request): Json<LoginRequest>,
+) -> impl IntoResponse {
+    let account = match Account::get_by_username(&state.storage, request.username.trim()).await {
+        Ok(account) => account,
+        Err(e) => {
+            error!("❌ [login] 获取账号失败 - 错误: {}", e);
+            return (StatusCode::UNAUTHORIZED, "用户名或密码错误").into_response();
+        }
+    };
+    if !account.verify_password(&request.password) {
+        return (StatusCode::UNAUTHORIZED, "用户名或密码错误").into_response();
+    }
+    match state.jwt.sign(account.player_id, &account.player_name, account.token_version) {
+        Ok(token) => {
+            let json = json!({
+                "token": token,
+                "player_id": account.player_id,
+                "player_name": account.player_name,
+            });
+            (StatusCode::OK, Json(json)).into_response()
+        }
+        Err(e) => {
+            error!("❌ [login] 签发token失败 - 错误: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "登录失败").into_response()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChangePasswordRequest {
+    pub old_password: String,
+    pub new_password: String,
+}
+pub async fn change_password(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<ChangePasswordRequest>,
+) -> impl IntoResponse {
+    if request.new_password.len() < MIN_PASSWORD_LEN {
+        return (StatusCode::BAD_REQUEST, "密码长度不足").into_response();
+    }
+    let account = match Account::get(&state.storage, auth.player_id).await {
+        Ok(account) => account,
+        Err(e) => {
+            error!("❌ [change_password] 获取账号失败 - 错误: {}", e);
+            return (StatusCode::BAD_REQUEST, "账号不存在").into_response();
+        }
+    };
+    if !account.verify_password(&request.old_password) {
+        return (StatusCode::UNAUTHORIZED, "原密码错误").into_response();
+    }
+    if let Err(e) = Account::set_password(&state.storage, auth.player_id, &request.new_password).await {
+        error!("❌ [change_password] 修改密码失败 - 错误: {}", e);
+        return (StatusCode::BAD_REQUEST, "密码修改失败").into_response();
+    }
+    // 修改密码后旧 token 全部失效（包括本次请求使用的 token），返回新 token 供当前客户端继续使用
+    let token = match Account::get(&state.storage, auth.player_id).await {
+        Ok(account) => state.jwt.sign(account.player_id, &account.player_name, account.token_version),
+        Err(e) => Err(e),
+    };
+    match token {
+        Ok(token) => {
+            let json = json!({
+                "token": token,
+                "content": "密码修改成功",
+            });
+            (StatusCode::OK, Json(json)).into_response()
+        }
+        Err(e) => {
+            error!("❌ [change_password] 签发token失败 - 错误: {}", e);
+            (StatusCode::OK, "密码修改成功，请重新登录").into_response()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ResetPasswordRequest {
+    pub player_id: i32,
+    pub new_password: String,
+}
+// 管理员重置任意玩家的密码，该玩家已登录的 token 全部失效
+pub async fn reset_password(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<ResetPasswordRequest>,
+) -> impl IntoResponse {
+    match Account::get(&state.storage, auth.player_id).await {
+        Ok(admin) if admin.is_admin => {}
+        Ok(_) => {
+            return (StatusCode::FORBIDDEN, "没有管理员权限").into_response();
+        }
+        Err(e) => {
+            error!("❌ [reset_password] 获取账号失败 - 错误: {}", e);
+            return (StatusCode::FORBIDDEN, "没有管理员权限").into_response();
+        }
+    }
+    if request.new_password.len() < MIN_PASSWORD_LEN {
+        return (StatusCode::BAD_REQUEST, "密码长度不足").into_response();
+    }
+    match Account::set_password(&state.storage, request.player_id, &request.new_password).await {
+        Ok(_) => (StatusCode::OK, "密码重置成功").into_response(),
+        Err(e) => {
+            error!("❌ [reset_password] 重置密码失败 - 错误: {}", e);
+            (StatusCode::BAD_REQUEST, "密码重置失败").into_response()
+        }
+    }
+}
+
+/// 定期清理过期游客，没有新的游客登录时也不会一直占用内存
+pub async fn purge_guests(state: AppState) {
+    let period = Duration::from_secs(state.guest_config.purge_interval_secs.max(1));
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        Guest::purge_expired(&state.guests);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuestLoginRequest {
+    pub player_name: String,
+}
+// 游客登录：分配临时 id 并签发短期 token，无需注册即可创建/加入房间
+pub async fn guest_login(
+    State(state): State<AppState>,
+    Json(request): Json<GuestLoginRequest>,
+) -> impl IntoResponse {
+    let player_name = request.player_name.trim().to_string();
+    if player_name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "玩家名称不能为空").into_response();
+    }
+    Guest::purge_expired(&state.guests);
+    // 游客只保存在内存中，限制数量，并且在分配 id 之前拒绝，避免白白消耗玩家 id
+    if state.guests.len() >= state.guest_config.max_guests {
+        error!("❌ [guest_login] 游客人数已达上限: {}", state.guest_config.max_guests);
+        return (StatusCode::SERVICE_UNAVAILABLE, "游客人数已达上限，请稍后再试").into_response();
+    }
+    let player_id = match Guest::allocate_id(&state.storage).await {
+        Ok(player_id) => player_id,
+        Err(e) => {
+            error!("❌ [guest_login] 分配游客id失败 - 错误: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "游客登录失败").into_response();
+        }
+    };
+    let (token, expires_at) = match state.jwt.sign_guest(player_id, &player_name) {
+        Ok(signed) => signed,
+        Err(e) => {
+            error!("❌ [guest_login] 签发token失败 - 错误: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "游客登录失败").into_response();
+        }
+    };
+    state
+        .guests
+        .insert(player_id, Guest::new(player_id, player_name.clone(), expires_at));
+    let json = json!({
+        "token": token,
+        "player_id": player_id,
+        "player_name": player_name,
+        "expires_at": expires_at,
+    });
+    (StatusCode::OK, Json(json)).into_response()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpgradeGuestRequest {
+    pub username: String,
+    pub password: String,
+}
+// 游客升级为正式账号：沿用游客 id，因此房间成员身份不变，内存中的好友关系写入数据库
+pub async fn upgrade_guest(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<UpgradeGuestRequest>,
+) -> impl IntoResponse {
+    if !auth.guest {
+        return (StatusCode::BAD_REQUEST, "当前不是游客").into_response();
+    }
+    if request.username.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "用户名不能为空").into_response();
+    }
+    if request.password.len() < MIN_PASSWORD_LEN {
+        return (StatusCode::BAD_REQUEST, "密码长度不足").into_response();
+    }
+    let Some(guest) = state.guests.get(&auth.player_id).map(|guest| guest.clone()) else {
+        return (StatusCode::UNAUTHORIZED, "游客身份已过期").into_response();
+    };
+    if let Err(e) = Account::register_with_id(
+        &state.storage,
+        guest.player_id,
+        request.username.trim(),
+        &guest.player_name,
+        &request.password,
+    )
+    .await
+    {
+        error!("❌ [upgrade_guest] 注册失败 - 错误: {}", e);
+        return (StatusCode::BAD_REQUEST, "注册失败").into_response();
+    }
+    state.guests.remove(&guest.player_id);
+    // 与正式玩家的好友关系写入 friend_mapping；与其他游客的关系仍保存在对方的游客记录中
+    for friend_id in guest.friend_ids {
+        if state.guests.contains_key(&friend_id) {
+            continue;
+        }
+        if let Err(e) = Friend::add_friend(&state.storage, guest.player_id, friend_id).await {
+            error!(
+                "❌ [upgrade_guest] 迁移好友关系失败 - friend_id: {}, 错误: {}",
+                friend_id, e
+            );
+        }
+    }
+    match state.jwt.sign(guest.player_id, &guest.player_name, 0) {
+        Ok(token) => {
+            let json = json!({
+                "token": token,
+                "player_id": guest.player_id,
+                "player_name": guest.player_name,
+            });
+            (StatusCode::OK, Json(json)).into_response()
+        }
+        Err(e) => {
+            error!("❌ [upgrade_guest] 签发token失败 - 错误: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "升级失败").into_response()
+        }
+    }
+}
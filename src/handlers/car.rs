@@ -3,87 +3,96 @@ use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
 
-use crate::{AppState, ChangeCarRequest, MessageType};
+use crate::{AppState, AuthPlayer, ChangeCarRequest, MessageType, Room, RoomChannel};
 use axum::Json;
 use tracing::debug;
 use tracing::error;
 
 pub async fn change_car(
     State(state): State<AppState>,
+    auth: AuthPlayer,
     Json(request): Json<ChangeCarRequest>,
 ) -> impl IntoResponse {
-    let room_info = (*state).room_info.get_mut(&request.room_id);
-    let room_info_clone;
-    match room_info {
-        Some(mut room) => {
-            (*room).cars.iter_mut().for_each(|car| {
-                car.player_ids.retain(|id| *id != request.player_id);
-                if car.car_id == request.car_id {
-                    car.player_ids.push(request.player_id);
-                }
-            });
-            room_info_clone = room.clone();
-        }
-        None => {
-            return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
-        }
+    let player_id = auth.player_id;
+    let Some(handle) = state.room(&request.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    let result = handle
+        .update(move |room, channel| {
+            if !room.players.iter().any(|p| p.player_id == player_id) {
+                return Err((StatusCode::FORBIDDEN, "玩家不在房间中"));
+            }
+            if !room.phase.is_idle() {
+                return Err((StatusCode::CONFLICT, "比赛进行中"));
+            }
+            // 换到没有人乘坐的车辆时沿用当前车辆的皮肤
+            let skin_id = room
+                .cars
+                .iter()
+                .find(|car| car.player_ids.contains(&player_id))
+                .map_or(0, |car| car.skin_id);
+            room.board_car(player_id, request.car_id, skin_id)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            broadcast_sync(channel, room)
+        })
+        .await;
+    match result {
+        Some(Ok(())) => (StatusCode::OK, "车辆更换成功").into_response(),
+        Some(Err(e)) => e.into_response(),
+        None => (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
     }
-    match (*state).room_broadcast_couple.get(&request.room_id) {
-        Some(couple) => {
-            match couple.0.send(MessageType::Sync(room_info_clone)) {
-                Ok(_) => {
-                    debug!("✅ [broadcast_to_ws] 同步消息广播成功");
-                }
-                Err(e) => {
-                    error!("❌ [broadcast_to_ws] 同步消息广播失败 - 错误: {}", e);
-                    return (StatusCode::BAD_REQUEST, "房间退出失败").into_response();
-                }
-            };
+}
+
+// 车辆变化后同步房间
+fn broadcast_sync(channel: &RoomChannel, room: &Room) -> Result<(), (StatusCode, &'static str)> {
+    match channel.send(MessageType::Sync(Box::new(room.clone()))) {
+        Ok(_) => {
+            debug!("✅ [broadcast_to_ws] 同步消息广播成功");
+            Ok(())
         }
-        None => {
-            return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+        Err(e) => {
+            error!("❌ [broadcast_to_ws] 同步消息广播失败 - 错误: {}", e);
+            Err((StatusCode::BAD_REQUEST, "房间退出失败"))
         }
     }
-    (StatusCode::OK, "车辆更换成功").into_response()
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChangeCarSkinRequest {
-    pub room_id: i32,
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
     pub car_id: i32,
     pub skin_id: i32,
 }
 
 pub async fn change_car_skin(
     State(state): State<AppState>,
+    auth: AuthPlayer,
     Json(request): Json<ChangeCarSkinRequest>,
 ) -> impl IntoResponse {
-    let room_info = (*state).room_info.get_mut(&request.room_id);
-    match room_info {
-        Some(mut room) => {
-            (*room).cars.iter_mut().for_each(|car| {
-                if car.car_id == request.car_id {
-                    car.skin_id = request.skin_id;
-                }
-            });
-            match (*state).room_broadcast_couple.get(&request.room_id) {
-                Some(couple) => match couple.0.send(MessageType::Sync(room.clone())) {
-                    Ok(_) => {
-                        debug!("✅ [broadcast_to_ws] 同步消息广播成功");
-                        return (StatusCode::OK, "车辆皮肤更换成功").into_response();
-                    }
-                    Err(e) => {
-                        error!("❌ [broadcast_to_ws] 同步消息广播失败 - 错误: {}", e);
-                        return (StatusCode::BAD_REQUEST, "房间退出失败").into_response();
-                    }
-                },
-                None => {
-                    return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
-                }
+    let Some(handle) = state.room(&request.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    let result = handle
+        .update(move |room, channel| {
+            if !room.phase.is_idle() {
+                return Err((StatusCode::CONFLICT, "比赛进行中"));
             }
-        }
-        None => {
-            return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
-        }
+            // 只能修改自己所在车辆的皮肤
+            let Some(car) = room
+                .cars
+                .iter_mut()
+                .find(|car| car.car_id == request.car_id && car.player_ids.contains(&auth.player_id))
+            else {
+                return Err((StatusCode::FORBIDDEN, "玩家不在该车辆中"));
+            };
+            car.skin_id = request.skin_id;
+            broadcast_sync(channel, room)
+        })
+        .await;
+    match result {
+        Some(Ok(())) => (StatusCode::OK, "车辆皮肤更换成功").into_response(),
+        Some(Err(e)) => e.into_response(),
+        None => (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
     }
 }
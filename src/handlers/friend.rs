@@ -4,58 +4,66 @@ use log::info;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tracing::error;
-use crate::{AppState, Friend};
+use crate::{AppState, AuthPlayer, Friend, Guest, SimplePlayer};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AddFriendRequest {
-    pub master_id: i32,
     pub friend_id: i32,
 }
 
 pub async fn add_friend(
     State(state): State<AppState>,
+    auth: AuthPlayer,
     Json(request): Json<AddFriendRequest>,
 ) -> impl IntoResponse {
-    match Friend::add_friend(&state.pool, request.master_id, request.friend_id).await {
-        Ok(_) => {
-            return (StatusCode::OK, "好友添加成功").into_response();
+    // 涉及游客的好友关系保存在内存中
+    if auth.guest || state.guests.contains_key(&request.friend_id) {
+        if !state.guests.contains_key(&request.friend_id)
+            && SimplePlayer::get_player(&state.storage, request.friend_id).await.is_err()
+        {
+            return (StatusCode::BAD_REQUEST, "添加好友失败").into_response();
         }
+        Guest::add_friend(&state.guests, auth.player_id, request.friend_id);
+        return (StatusCode::OK, "好友添加成功").into_response();
+    }
+    match Friend::add_friend(&state.storage, auth.player_id, request.friend_id).await {
+        Ok(_) => (StatusCode::OK, "好友添加成功").into_response(),
         Err(e) => {
             error!("❌ [add_friend] 添加好友失败 - 错误: {}", e);
-            return (StatusCode::BAD_REQUEST, "添加好友失败").into_response();
+            (StatusCode::BAD_REQUEST, "添加好友失败").into_response()
         }
     }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RemoveFriendRequest {
-    pub master_id: i32,
     pub friend_id: i32,
 }
 pub async fn remove_friend(
     State(state): State<AppState>,
+    auth: AuthPlayer,
     Json(request): Json<RemoveFriendRequest>,
 ) -> impl IntoResponse {
-    match Friend::remove_friend(&state.pool, request.master_id, request.friend_id).await {
-        Ok(_) => {
+    if auth.guest || state.guests.contains_key(&request.friend_id) {
+        if Guest::remove_friend(&state.guests, auth.player_id, request.friend_id) {
             return (StatusCode::OK, "好友删除成功").into_response();
         }
+        return (StatusCode::BAD_REQUEST, "删除好友失败").into_response();
+    }
+    match Friend::remove_friend(&state.storage, auth.player_id, request.friend_id).await {
+        Ok(_) => (StatusCode::OK, "好友删除成功").into_response(),
         Err(e) => {
             error!("❌ [remove_friend] 删除好友失败 - 错误: {}", e);
-            return (StatusCode::BAD_REQUEST, "删除好友失败").into_response();
+            (StatusCode::BAD_REQUEST, "删除好友失败").into_response()
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct GetFriendsRequest {
-    pub master_id: i32,
-}
 pub async fn get_friends(
     State(state): State<AppState>,
-    Json(request): Json<GetFriendsRequest>,
+    auth: AuthPlayer,
 ) -> impl IntoResponse {
-    match Friend::get_friends(&state.pool, request.master_id).await {
+    match Friend::get_all_friends(&state.storage, &state.guests, auth.player_id).await {
         Ok(friends) => {
             let json_response = json!({
                 "master_id": friends.master_id,
@@ -71,4 +79,4 @@ pub async fn get_friends(
             })))
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,135 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, error};
+
+use crate::{AppState, AuthPlayer, Friend, RoomInvite, RoomVisibility, ServerMessage};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InviteFriendRequest {
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
+    pub friend_id: i32,
+}
+
+// 邀请好友加入自己所在的房间，邀请实时推送到好友打开的连接
+pub async fn invite_friend(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<InviteFriendRequest>,
+) -> impl IntoResponse {
+    RoomInvite::purge_expired(&state.room_invites);
+    match state
+        .room(&request.room_id)
+        .map(|handle| handle.read(|room| room.has_member(auth.player_id)))
+    {
+        Some(true) => {}
+        Some(false) => return (StatusCode::FORBIDDEN, "玩家不在房间中").into_response(),
+        None => return (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
+    }
+    match Friend::get_all_friends(&state.storage, &state.guests, auth.player_id).await {
+        Ok(friends) if friends.friend_ids.iter().any(|f| f.player_id == request.friend_id) => {}
+        Ok(_) => return (StatusCode::FORBIDDEN, "对方不是你的好友").into_response(),
+        Err(e) => {
+            error!("❌ [invite_friend] 获取好友失败 - 错误: {}", e);
+            return (StatusCode::BAD_REQUEST, "获取好友失败").into_response();
+        }
+    }
+
+    let invite = RoomInvite::new(
+        request.room_id,
+        auth.player_id,
+        auth.player_name,
+        request.friend_id,
+        state.room_config.invite_ttl_secs,
+    );
+    // 好友当前不在线时邀请仍然保留，可通过 /getinvites 获取
+    let delivered = state.notify_player(invite.to_id, invite.to_server_message());
+    debug!(
+        "✉️ [invite_friend] 玩家 {} 邀请 {} 加入房间 {}，已推送: {}",
+        invite.from_id, invite.to_id, invite.room_id, delivered
+    );
+    let json = json!({
+        "invite_id": invite.invite_id,
+        "expires_at": invite.expires_at,
+        "delivered": delivered,
+    });
+    state.room_invites.insert(invite.invite_id.clone(), invite);
+    (StatusCode::OK, Json(json)).into_response()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RespondInviteRequest {
+    pub invite_id: String,
+    pub accept: bool,
+}
+
+// 接受或拒绝邀请；接受时返回加入房间的参数，房主邀请加入非公开房间时附带一次性邀请码
+pub async fn respond_invite(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<RespondInviteRequest>,
+) -> impl IntoResponse {
+    let invite = match state
+        .room_invites
+        .remove_if(&request.invite_id, |_, invite| invite.to_id == auth.player_id)
+    {
+        Some((_, invite)) => invite,
+        None => return (StatusCode::BAD_REQUEST, "邀请不存在").into_response(),
+    };
+    if invite.is_expired() {
+        return (StatusCode::GONE, "邀请已过期").into_response();
+    }
+    let answered = ServerMessage::InviteAnswered {
+        invite_id: invite.invite_id.clone(),
+        player_id: auth.player_id,
+        accepted: request.accept,
+    };
+    if !request.accept {
+        state.notify_player(invite.from_id, answered);
+        return (StatusCode::OK, "已拒绝邀请").into_response();
+    }
+
+    let Some(handle) = state.room(&invite.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    // 在同一个命令中确认邀请人仍在房间并生成邀请码；只有房主的邀请附带邀请码，
+    // 其他成员的邀请不能绕过房间密码和仅好友限制
+    let from_id = invite.from_id;
+    let invite_code = match handle
+        .update(move |room, _| {
+            if !room.has_member(from_id) {
+                return Err("邀请人已不在房间中");
+            }
+            Ok((room.visibility != RoomVisibility::Public && room.is_owner(from_id)).then(|| {
+                let code = uuid::Uuid::new_v4().simple().to_string();
+                room.invite_codes.insert(code.clone());
+                code
+            }))
+        })
+        .await
+    {
+        Some(Ok(invite_code)) => invite_code,
+        Some(Err(e)) => return (StatusCode::CONFLICT, e).into_response(),
+        None => return (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
+    };
+    state.notify_player(invite.from_id, answered);
+    let json = json!({
+        "room_id": invite.room_id,
+        "invite_code": invite_code,
+    });
+    (StatusCode::OK, Json(json)).into_response()
+}
+
+// 获取发给自己的未过期邀请
+pub async fn get_invites(State(state): State<AppState>, auth: AuthPlayer) -> impl IntoResponse {
+    RoomInvite::purge_expired(&state.room_invites);
+    let invites: Vec<RoomInvite> = state
+        .room_invites
+        .iter()
+        .filter(|invite| invite.to_id == auth.player_id)
+        .map(|invite| invite.clone())
+        .collect();
+    (StatusCode::OK, Json(invites)).into_response()
+}
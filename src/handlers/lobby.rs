@@ -0,0 +1,172 @@
+use axum::{
+    Json,
+    extract::{
+        Query, State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use crate::{
+    AppState, AuthPlayer, ConnectionKind, Room, RoomListPage, RoomSummary, RoomVisibility, SUPPORTED_SUBPROTOCOLS,
+    ServerMessage, WireFormat,
+};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+// 大厅筛选条件，所有字段均可省略
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RoomListQuery {
+    // 页码，从 1 开始
+    pub page: Option<usize>,
+    pub page_size: Option<usize>,
+    pub weather_id: Option<i32>,
+    pub background_id: Option<i32>,
+    // 房间容量（max_players）
+    pub max_players: Option<usize>,
+    // 当前人数范围
+    pub min_player_count: Option<usize>,
+    pub max_player_count: Option<usize>,
+    // 房主名称，包含匹配
+    pub host_name: Option<String>,
+    // 只显示还有空位的房间
+    pub has_free_seats: Option<bool>,
+}
+
+impl RoomListQuery {
+    /// 房间是否满足筛选条件；只列出公开房间
+    pub fn matches(&self, room: &Room) -> bool {
+        if room.visibility != RoomVisibility::Public {
+            return false;
+        }
+        let summary = RoomSummary::from(room);
+        self.weather_id.is_none_or(|id| summary.weather_id == id)
+            && self.background_id.is_none_or(|id| summary.background_id == id)
+            && self.max_players.is_none_or(|n| summary.max_players == n)
+            && self.min_player_count.is_none_or(|n| summary.player_count >= n)
+            && self.max_player_count.is_none_or(|n| summary.player_count <= n)
+            && self.has_free_seats.is_none_or(|free| free != room.is_full())
+            && self.host_name.as_deref().is_none_or(|name| {
+                summary
+                    .host_name
+                    .as_deref()
+                    .is_some_and(|host| host.contains(name))
+            })
+    }
+
+    fn page(&self) -> usize {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// 按筛选条件分页列出房间，按房间码排序保证翻页稳定
+pub fn list_rooms(state: &AppState, query: &RoomListQuery) -> RoomListPage {
+    let mut rooms: Vec<RoomSummary> = state
+        .rooms
+        .iter()
+        .filter_map(|handle| {
+            handle.read(|room| query.matches(room).then(|| RoomSummary::from(room)))
+        })
+        .collect();
+    rooms.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+    let total = rooms.len();
+    let page = query.page();
+    let page_size = query.page_size();
+    let rooms = rooms
+        .into_iter()
+        .skip((page - 1) * page_size)
+        .take(page_size)
+        .collect();
+    RoomListPage {
+        rooms,
+        total,
+        page,
+        page_size,
+    }
+}
+
+// 大厅房间列表
+pub async fn get_rooms(
+    State(state): State<AppState>,
+    _auth: AuthPlayer,
+    Query(query): Query<RoomListQuery>,
+) -> impl IntoResponse {
+    Json(list_rooms(&state, &query))
+}
+
+// 大厅实时房间列表，房间创建、加入、离开时推送最新一页；同时推送发给该玩家的好友邀请
+pub async fn lobby_websocket_handler(
+    ws: WebSocketUpgrade,
+    auth: AuthPlayer,
+    Query(query): Query<RoomListQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    debug!(
+        "🔌 [lobby_websocket_handler] 玩家 {} 订阅大厅，筛选条件: {:?}",
+        auth.player_id, query
+    );
+    let ws = ws.protocols(SUPPORTED_SUBPROTOCOLS);
+    let format = WireFormat::from_subprotocol(ws.selected_protocol());
+    ws.on_upgrade(move |socket| {
+        handle_lobby_websocket(socket, auth.player_id, query, format, state)
+    })
+}
+
+async fn handle_lobby_websocket(
+    mut socket: WebSocket,
+    player_id: i32,
+    query: RoomListQuery,
+    format: WireFormat,
+    state: AppState,
+) {
+    let mut lobby_rx = state.lobby_notify.subscribe();
+    let (_connection, mut inbox) = state
+        .connections
+        .register(player_id, ConnectionKind::Lobby, None);
+    'push: loop {
+        let page = list_rooms(&state, &query);
+        if socket
+            .send(format.encode(&ServerMessage::RoomList(page)))
+            .await
+            .is_err()
+        {
+            error!("❌ [lobby_websocket] 房间列表发送失败");
+            break;
+        }
+        // 等待下一次房间变化；大厅连接不处理客户端消息（Ping 由 axum 自动回复）
+        loop {
+            tokio::select! {
+                changed = lobby_rx.changed() => {
+                    if changed.is_err() {
+                        break 'push;
+                    }
+                    continue 'push;
+                }
+                message = socket.recv() => {
+                    if let Some(Ok(Message::Close(_)) | Err(_)) | None = message {
+                        break 'push;
+                    }
+                }
+                // 发给该玩家的个人消息（好友邀请等）
+                event = inbox.recv() => match event {
+                    Some(event) => {
+                        if socket.send(format.encode(&event)).await.is_err() {
+                            break 'push;
+                        }
+                    }
+                    None => break 'push,
+                },
+            }
+        }
+    }
+    debug!("🔌 [lobby_websocket] 大厅连接已关闭");
+}
@@ -0,0 +1,322 @@
+use std::{
+    cmp::Reverse,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{
+        Query, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket},
+    },
+    response::IntoResponse,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use tokio::{
+    sync::oneshot,
+    time::{interval, sleep},
+};
+use tracing::{debug, error};
+
+use crate::{
+    AppState, AuthPlayer, ConnectionKind, MatchCancelReason, Room, RoomVisibility, SUPPORTED_SUBPROTOCOLS,
+    ServerMessage, WireFormat, open_room, player_rating,
+};
+
+// 排队期间定期重试匹配，让扩大后的分数窗口生效
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// 排队偏好，天气 / 背景省略表示不限
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatchPreferences {
+    pub car_id: i32,
+    pub weather_id: Option<i32>,
+    pub background_id: Option<i32>,
+}
+
+impl MatchPreferences {
+    /// 房间设置是否满足偏好
+    pub fn accepts(&self, room: &Room) -> bool {
+        self.weather_id.is_none_or(|id| id == room.weather_id)
+            && self.background_id.is_none_or(|id| id == room.background_id)
+    }
+}
+
+// 匹配结果，由匹配方通过 oneshot 发给排队的连接
+#[derive(Debug, Clone)]
+pub struct MatchAssignment {
+    pub room_id: String,
+    pub weather_id: i32,
+    pub background_id: i32,
+}
+
+// 排队中的玩家
+#[derive(Debug)]
+pub struct MatchTicket {
+    // 区分同一玩家的多次排队，重新排队会替换旧的排队
+    pub ticket_id: uuid::Uuid,
+    pub preferences: MatchPreferences,
+    pub rating: i32,
+    pub enqueued_at: Instant,
+    notify: oneshot::Sender<MatchAssignment>,
+}
+
+impl MatchTicket {
+    /// 新的排队，同时返回接收匹配结果的一端
+    pub fn new(preferences: MatchPreferences, rating: i32) -> (Self, oneshot::Receiver<MatchAssignment>) {
+        let (notify, assignment_rx) = oneshot::channel();
+        let ticket = Self {
+            ticket_id: uuid::Uuid::new_v4(),
+            preferences,
+            rating,
+            enqueued_at: Instant::now(),
+            notify,
+        };
+        (ticket, assignment_rx)
+    }
+}
+
+/// 为排队玩家寻找房间：优先加入有空位、设置符合偏好且平均分在窗口内的公开房间
+/// （分差小的优先，其次人多的优先），否则与偏好兼容、分数在窗口内的排队玩家凑够 min_players 后新建房间。
+/// 分数窗口随排队时间扩大。
+/// 分配后从队列移除并通知对应连接，返回是否分配成功。
+/// 分配不预留座位，客户端加入时房间已满需要重新排队
+pub fn try_match(state: &AppState, player_id: i32) -> bool {
+    let (preferences, rating, window) = match state.match_queue.get(&player_id) {
+        Some(ticket) => (
+            ticket.preferences.clone(),
+            ticket.rating,
+            state.match_config.rating_window(ticket.enqueued_at.elapsed()),
+        ),
+        None => return false,
+    };
+    // 空房间（刚创建、玩家尚未连接）视为处于窗口边缘
+    let rating_gap = |room: &Room| match room.average_rating() {
+        Some(average) => (average - rating as f64).abs().round() as i32,
+        None => window,
+    };
+
+    let open_room_id = state
+        .rooms
+        .iter()
+        .map(|handle| handle.snapshot())
+        .filter(|room| {
+            room.visibility == RoomVisibility::Public
+                && room.phase.is_idle()
+                && !room.banned_ids.contains(&player_id)
+                && !room.is_full()
+                && preferences.accepts(room)
+                && rating_gap(room) <= window
+        })
+        .min_by_key(|room| (rating_gap(room), Reverse(room.players.len())))
+        .map(|room| MatchAssignment {
+            room_id: room.room_id.clone(),
+            weather_id: room.weather_id,
+            background_id: room.background_id,
+        });
+    if let Some(assignment) = open_room_id {
+        return assign(state, player_id, assignment);
+    }
+
+    // 按排队先后贪心组队，组内天气 / 背景需要一致
+    let mut tickets: Vec<(i32, Instant, MatchPreferences)> = state
+        .match_queue
+        .iter()
+        .filter(|t| (t.rating - rating).abs() <= window)
+        .map(|t| (*t.key(), t.enqueued_at, t.preferences.clone()))
+        .collect();
+    tickets.sort_by_key(|(_, enqueued_at, _)| *enqueued_at);
+    let mut weather_id = preferences.weather_id;
+    let mut background_id = preferences.background_id;
+    let mut group = vec![player_id];
+    for (id, _, other) in tickets {
+        if group.len() >= state.room_config.max_players {
+            break;
+        }
+        if id == player_id
+            || !compatible(weather_id, other.weather_id)
+            || !compatible(background_id, other.background_id)
+        {
+            continue;
+        }
+        weather_id = weather_id.or(other.weather_id);
+        background_id = background_id.or(other.background_id);
+        group.push(id);
+    }
+    if group.len() < state.match_config.min_players {
+        return false;
+    }
+
+    let weather_id = weather_id.unwrap_or(state.match_config.default_weather_id);
+    let background_id = background_id.unwrap_or(state.match_config.default_background_id);
+    let room_id = open_room(
+        state,
+        player_id,
+        weather_id,
+        background_id,
+        state.room_config.max_players,
+        RoomVisibility::Public,
+        None,
+    );
+    debug!(
+        "🎯 [try_match] 新建房间 {} 分配给排队玩家 {:?}",
+        room_id, group
+    );
+    let mut assigned = false;
+    for id in group {
+        assigned |= assign(
+            state,
+            id,
+            MatchAssignment {
+                room_id: room_id.clone(),
+                weather_id,
+                background_id,
+            },
+        ) && id == player_id;
+    }
+    assigned
+}
+
+fn compatible(a: Option<i32>, b: Option<i32>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+// 从队列移除并通知；其他匹配已经先移除了该玩家时返回 false
+fn assign(state: &AppState, player_id: i32, assignment: MatchAssignment) -> bool {
+    match state.match_queue.remove(&player_id) {
+        Some((_, ticket)) => ticket.notify.send(assignment).is_ok(),
+        None => false,
+    }
+}
+
+// 快速匹配：排队后等待分配房间，超时或取消时收到 match_cancelled
+pub async fn match_websocket_handler(
+    ws: WebSocketUpgrade,
+    auth: AuthPlayer,
+    Query(preferences): Query<MatchPreferences>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    debug!(
+        "🔌 [match_websocket_handler] 玩家 {} 请求快速匹配，偏好: {:?}",
+        auth.player_id, preferences
+    );
+    if preferences.weather_id.is_some_and(|id| !state.catalog.has_weather(id)) {
+        return (StatusCode::BAD_REQUEST, "天气不存在").into_response();
+    }
+    if preferences
+        .background_id
+        .is_some_and(|id| !state.catalog.has_background(id))
+    {
+        return (StatusCode::BAD_REQUEST, "背景不存在").into_response();
+    }
+    let ws = ws.protocols(SUPPORTED_SUBPROTOCOLS);
+    let format = WireFormat::from_subprotocol(ws.selected_protocol());
+    let rating = player_rating(&state, auth.player_id, auth.guest).await;
+    ws.on_upgrade(move |socket| {
+        handle_match_websocket(socket, auth.player_id, preferences, rating, format, state)
+    })
+}
+
+async fn handle_match_websocket(
+    mut socket: WebSocket,
+    player_id: i32,
+    preferences: MatchPreferences,
+    rating: i32,
+    format: WireFormat,
+    state: AppState,
+) {
+    let car_id = preferences.car_id;
+    let _connection = state.connections.register(player_id, ConnectionKind::Match, None).0;
+    let (ticket, mut assignment_rx) = MatchTicket::new(preferences, rating);
+    let ticket_id = ticket.ticket_id;
+    // 同一玩家重新排队时替换旧的排队，旧连接收到 cancelled
+    state.match_queue.insert(player_id, ticket);
+    let timeout_secs = state.match_config.timeout_secs;
+    if socket
+        .send(format.encode(&ServerMessage::MatchQueued { timeout_secs }))
+        .await
+        .is_err()
+    {
+        remove_ticket(&state, player_id, ticket_id);
+        return;
+    }
+
+    // 房间列表变化时（新建房间、有人离开）以及定期重新尝试匹配
+    let mut lobby_rx = state.lobby_notify.subscribe();
+    try_match(&state, player_id);
+    let deadline = sleep(Duration::from_secs(timeout_secs));
+    tokio::pin!(deadline);
+    let mut retry = interval(RETRY_INTERVAL);
+    let outcome = loop {
+        tokio::select! {
+            assignment = &mut assignment_rx => {
+                break match_outcome(assignment.ok(), car_id);
+            }
+            changed = lobby_rx.changed() => {
+                if changed.is_ok() {
+                    try_match(&state, player_id);
+                }
+            }
+            _ = retry.tick() => {
+                try_match(&state, player_id);
+            }
+            _ = &mut deadline => {
+                if remove_ticket(&state, player_id, ticket_id) {
+                    break ServerMessage::MatchCancelled { reason: MatchCancelReason::Timeout };
+                }
+                // 超时的同时已被分配或取消，结果马上会到达
+                break match_outcome((&mut assignment_rx).await.ok(), car_id);
+            }
+            message = socket.recv() => {
+                if let Some(Ok(Message::Close(_)) | Err(_)) | None = message {
+                    debug!("🔌 [match_websocket] 玩家 {} 断开，取消排队", player_id);
+                    remove_ticket(&state, player_id, ticket_id);
+                    return;
+                }
+            }
+        }
+    };
+
+    if socket.send(format.encode(&outcome)).await.is_err() {
+        error!("❌ [match_websocket] 匹配结果发送失败 - player_id: {}", player_id);
+        return;
+    }
+    let close_frame = Message::Close(Some(CloseFrame {
+        code: 1000,
+        reason: "Match finished".into(),
+    }));
+    let _ = socket.send(close_frame).await;
+}
+
+fn match_outcome(assignment: Option<MatchAssignment>, car_id: i32) -> ServerMessage {
+    match assignment {
+        Some(assignment) => ServerMessage::MatchFound {
+            room_id: assignment.room_id,
+            car_id,
+            weather_id: assignment.weather_id,
+            background_id: assignment.background_id,
+        },
+        None => ServerMessage::MatchCancelled {
+            reason: MatchCancelReason::Cancelled,
+        },
+    }
+}
+
+// 只移除本连接的排队，避免误删同一玩家新的排队
+fn remove_ticket(state: &AppState, player_id: i32, ticket_id: uuid::Uuid) -> bool {
+    state
+        .match_queue
+        .remove_if(&player_id, |_, ticket| ticket.ticket_id == ticket_id)
+        .is_some()
+}
+
+// 取消快速匹配，排队连接收到 match_cancelled
+pub async fn cancel_match(State(state): State<AppState>, auth: AuthPlayer) -> impl IntoResponse {
+    match state.match_queue.remove(&auth.player_id) {
+        Some(_) => (StatusCode::OK, "已取消匹配").into_response(),
+        None => (StatusCode::BAD_REQUEST, "不在匹配队列中").into_response(),
+    }
+}
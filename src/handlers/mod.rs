@@ -7,8 +7,22 @@ pub mod car;
 pub use car::*;
 pub mod friend;
 pub use friend::*;
-pub mod player;
-pub use player::*;
+mod account;
+pub use account::*;
+mod lobby;
+pub use lobby::*;
+mod matchmaking;
+pub use matchmaking::*;
+mod rating;
+pub use rating::*;
+mod phase;
+pub use phase::*;
+mod invite;
+pub use invite::*;
+mod session;
+pub use session::*;
+mod reconnect;
+pub use reconnect::*;
 
 
 
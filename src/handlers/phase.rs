@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use axum::{Json, extract::State, response::IntoResponse};
+use http::StatusCode;
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use crate::{AppState, AuthPlayer, MessageType, Room, RoomChannel, RoomPhase};
+
+#[derive(Debug, Deserialize)]
+pub struct SetReadyRequest {
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
+    pub ready: bool,
+}
+
+// 玩家切换准备状态
+pub async fn set_ready(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<SetReadyRequest>,
+) -> impl IntoResponse {
+    let Some(handle) = state.room(&request.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    let task_state = state.clone();
+    let result: Option<Result<(), &str>> = handle
+        .update(move |room, channel| {
+            let phase_changed = room.set_ready(auth.player_id, request.ready)?;
+            if phase_changed.is_some() {
+                announce_phase(&task_state, channel, room);
+            } else {
+                sync_room(channel, room);
+            }
+            Ok(())
+        })
+        .await;
+    match result {
+        Some(Ok(())) => (StatusCode::OK, "准备状态已更新").into_response(),
+        Some(Err(e)) => (StatusCode::CONFLICT, e).into_response(),
+        None => (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartRaceRequest {
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
+}
+
+// 房主开始比赛，全员准备后进入倒计时
+pub async fn start_race(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<StartRaceRequest>,
+) -> impl IntoResponse {
+    let Some(handle) = state.room(&request.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    let task_state = state.clone();
+    let result = handle
+        .update(move |room, channel| {
+            if !room.is_owner(auth.player_id) {
+                return Err((StatusCode::FORBIDDEN, "只有房主可以开始比赛"));
+            }
+            room.start_countdown()
+                .map_err(|e| (StatusCode::CONFLICT, e))?;
+            announce_phase(&task_state, channel, room);
+            Ok(())
+        })
+        .await;
+    match result {
+        Some(Ok(())) => (StatusCode::OK, "比赛即将开始").into_response(),
+        Some(Err(e)) => e.into_response(),
+        None => (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
+    }
+}
+
+/// 广播阶段变化和房间快照；Countdown 和 Results 由服务器在到期后自动推进，
+/// Racing 超时未提交成绩时中止回到 Lobby。
+/// 在房间任务中调用
+pub fn announce_phase(state: &AppState, channel: &RoomChannel, room_info: &Room) {
+    let (delay_secs, next) = match room_info.phase {
+        RoomPhase::Countdown => (Some(state.room_config.countdown_secs), RoomPhase::Racing),
+        RoomPhase::Racing => (Some(state.room_config.race_timeout_secs), RoomPhase::Lobby),
+        RoomPhase::Results => (Some(state.room_config.results_secs), RoomPhase::Lobby),
+        phase => (None, phase),
+    };
+    let deadline = delay_secs
+        .map(|secs| chrono::Utc::now().timestamp_millis() + (secs * 1000) as i64);
+    debug!(
+        "🚦 [announce_phase] 房间 {} 进入 {:?} 阶段",
+        room_info.room_id, room_info.phase
+    );
+
+    if let Err(e) = channel.send(MessageType::PhaseChanged(
+        room_info.room_id.clone(),
+        room_info.phase,
+        deadline,
+    )) {
+        error!("❌ [announce_phase] 阶段变化广播失败 - 错误: {}", e);
+    }
+    state.notify_lobby();
+
+    if let Some(secs) = delay_secs {
+        let state = state.clone();
+        let room_id = room_info.room_id.clone();
+        let epoch = room_info.phase_epoch;
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+            advance_phase(&state, &room_id, epoch, next).await;
+        });
+    }
+    sync_room(channel, room_info);
+}
+
+// 定时推进阶段；期间阶段已被其他操作改变（epoch 不同）或房间已销毁时放弃
+async fn advance_phase(state: &AppState, room_id: &str, epoch: u64, next: RoomPhase) {
+    let Some(handle) = state.room(room_id) else {
+        return;
+    };
+    let task_state = state.clone();
+    handle
+        .update(move |room, channel| {
+            if room.phase_epoch != epoch {
+                return;
+            }
+            let advanced = match (room.phase, next) {
+                (RoomPhase::Racing, RoomPhase::Lobby) => room.abort_race(),
+                _ => room.transition(next),
+            };
+            if advanced.is_ok() {
+                announce_phase(&task_state, channel, room);
+            }
+        })
+        .await;
+}
+
+/// 广播房间快照，在房间任务中调用
+pub fn sync_room(channel: &RoomChannel, room_info: &Room) {
+    if let Err(e) = channel.send(MessageType::Sync(Box::new(room_info.clone()))) {
+        error!("❌ [sync_room] 同步房间失败 - 错误: {}", e);
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+
+use axum::{Json, extract::State, response::IntoResponse};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, error};
+
+use crate::{AppState, AuthPlayer, DEFAULT_RATING, Rating, RoomPhase, announce_phase, sync_room};
+
+/// 查询玩家分数，游客或查询失败时使用初始分
+pub async fn player_rating(state: &AppState, player_id: i32, guest: bool) -> i32 {
+    if guest {
+        return DEFAULT_RATING;
+    }
+    match Rating::get(&state.storage, player_id).await {
+        Ok(rating) => rating,
+        Err(e) => {
+            error!(
+                "❌ [player_rating] 查询分数失败 - player_id: {}, 错误: {}",
+                player_id, e
+            );
+            DEFAULT_RATING
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportResultRequest {
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
+    // 完赛名次，第一名在前，必须都是房间内的玩家
+    pub finishing_order: Vec<i32>,
+}
+
+// 房主提交一局的完赛名次，结算分数并同步房间
+pub async fn report_result(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<ReportResultRequest>,
+) -> impl IntoResponse {
+    let finishing_order = request.finishing_order;
+    let Some(handle) = state.room(&request.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    let order = finishing_order.clone();
+    let result = handle
+        .update(move |room, _| {
+            if !room.is_owner(auth.player_id) {
+                return Err((StatusCode::FORBIDDEN, "只有房主可以提交成绩"));
+            }
+            let unique: HashSet<i32> = order.iter().copied().collect();
+            if order.len() < 2 || unique.len() != order.len() {
+                return Err((StatusCode::BAD_REQUEST, "名次列表无效"));
+            }
+            if !order
+                .iter()
+                .all(|id| room.players.iter().any(|p| p.player_id == *id))
+            {
+                return Err((StatusCode::BAD_REQUEST, "名次列表包含不在房间中的玩家"));
+            }
+            let epoch = room.claim_result().map_err(|e| (StatusCode::CONFLICT, e))?;
+            // 游客身份以加入房间时的 token 为准，游客记录过期或服务器重启后仍然不参与结算
+            let guest_ids: HashSet<i32> = room
+                .players
+                .iter()
+                .filter(|p| p.guest)
+                .map(|p| p.player_id)
+                .collect();
+            Ok((epoch, guest_ids))
+        })
+        .await;
+    let (epoch, guest_ids) = match result {
+        Some(Ok(claimed)) => claimed,
+        Some(Err(e)) => return e.into_response(),
+        None => return (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
+    };
+
+    // 分数写入成功后才进入 Results，失败时房间仍停留在 Racing，可以重新提交
+    let updated = match Rating::apply_finishing_order(&state.storage, &finishing_order, &guest_ids).await {
+        Ok(updated) => updated,
+        Err(e) => {
+            error!("❌ [report_result] 结算分数失败 - 错误: {}", e);
+            handle.update(move |room, _| room.release_result(epoch)).await;
+            return (StatusCode::INTERNAL_SERVER_ERROR, "结算分数失败").into_response();
+        }
+    };
+    debug!(
+        "🏁 [report_result] 房间 {} 结算完成: {:?}",
+        request.room_id, updated
+    );
+
+    // 同步房间内玩家的新分数；结算期间比赛已超时中止时只更新分数
+    let task_state = state.clone();
+    let ratings = updated.clone();
+    handle
+        .update(move |room, channel| {
+            for player in &mut room.players {
+                if let Some(rating) = ratings.get(&player.player_id) {
+                    player.rating = *rating;
+                }
+            }
+            if room.phase_epoch == epoch && room.transition(RoomPhase::Results).is_ok() {
+                announce_phase(&task_state, channel, room);
+            } else {
+                sync_room(channel, room);
+            }
+        })
+        .await;
+
+    let json = json!({
+        "room_id": request.room_id,
+        "ratings": updated,
+    });
+    (StatusCode::OK, Json(json)).into_response()
+}
@@ -0,0 +1,167 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    pin,
+    sync::{
+        Notify,
+        broadcast::{self, error::RecvError},
+    },
+    time::sleep,
+};
+use tracing::{debug, error};
+
+use crate::{AppState, MessageType, RoomEvent, close_room, depart, leave_room, sync_room};
+
+// 房间连接结束的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disconnect {
+    // 客户端发送了关闭帧，立即离开房间
+    Closed,
+    // 心跳超时或发送失败，保留座位等待重连
+    Lost,
+}
+
+// 掉线玩家的座位保留记录
+pub struct PendingResume {
+    pub room_id: String,
+    // 掉线的连接，宽限期结束时只移除属于它的记录
+    pub connection_id: u64,
+    // 掉线时房间消息的序号，重连时从这里开始补发
+    pub last_seq: u64,
+    resumed: Arc<Notify>,
+}
+
+// 房间任务处理断开连接的结果
+enum Departed {
+    // 玩家已经用新连接接回座位
+    Superseded,
+    // 保留座位，订阅房间广播等待宽限期结束
+    Held(broadcast::Receiver<RoomEvent>, Arc<Notify>),
+    // 离开房间，true 表示房间随之关闭
+    Left(Result<bool, &'static str>),
+}
+
+/// 房间连接结束：主动关闭或观战者断开时离开房间，玩家掉线时标记为重连中并保留座位
+pub async fn disconnect(state: &AppState, room_id: &str, player_id: i32, connection_id: u64, how: Disconnect) {
+    let Some(handle) = state.room(room_id) else {
+        return;
+    };
+    let task_state = state.clone();
+    let departed = handle
+        .update(move |room, channel| {
+            if !room.is_current_connection(player_id, connection_id) {
+                return Departed::Superseded;
+            }
+            if how == Disconnect::Lost && room.set_reconnecting(player_id, true) {
+                // 在房间任务中登记保留记录，重连请求不会早于登记到达
+                let pending = PendingResume {
+                    room_id: room.room_id.clone(),
+                    connection_id,
+                    last_seq: channel.last_seq(),
+                    resumed: Arc::new(Notify::new()),
+                };
+                let resumed = pending.resumed.clone();
+                task_state.reconnecting.insert(player_id, pending);
+                sync_room(channel, room);
+                return Departed::Held(channel.subscribe(), resumed);
+            }
+            Departed::Left(depart(&task_state, room, channel, player_id, None))
+        })
+        .await;
+    match departed {
+        None => {}
+        // 玩家已经用新连接接回座位，旧连接断开不影响房间
+        Some(Departed::Superseded) => debug!(
+            "🔁 [disconnect] 连接已被取代 - player_id: {}, connection_id: {}",
+            player_id, connection_id
+        ),
+        Some(Departed::Left(Ok(closed))) => {
+            if closed {
+                close_room(state, &handle);
+            }
+            state.notify_lobby();
+        }
+        // 通过 /quitroom 退出的玩家已经被移除
+        Some(Departed::Left(Err(e))) => {
+            debug!("🛑 [disconnect] 玩家 {} 离开房间 {}: {}", player_id, room_id, e)
+        }
+        Some(Departed::Held(rx, resumed)) => {
+            debug!(
+                "⏸️ [disconnect] 玩家掉线，保留座位 {} 秒 - player_id: {}, room_id: {}",
+                state.room_config.reconnect_grace_secs, player_id, room_id
+            );
+            tokio::spawn(hold_seat(
+                state.clone(),
+                rx,
+                room_id.to_string(),
+                player_id,
+                connection_id,
+                resumed,
+            ));
+        }
+    }
+}
+
+/// 重连时取回座位保留记录并停止计时，返回掉线时的消息序号；没有保留记录时返回 None
+pub fn claim_pending(state: &AppState, room_id: &str, player_id: i32) -> Option<u64> {
+    let (_, pending) = state
+        .reconnecting
+        .remove_if(&player_id, |_, pending| pending.room_id == room_id)?;
+    pending.resumed.notify_one();
+    Some(pending.last_seq)
+}
+
+// 宽限期内保留座位；到期仍未重连则离开房间
+async fn hold_seat(
+    state: AppState,
+    mut rx: broadcast::Receiver<RoomEvent>,
+    room_id: String,
+    player_id: i32,
+    connection_id: u64,
+    resumed: Arc<Notify>,
+) {
+    let grace = sleep(Duration::from_secs(state.room_config.reconnect_grace_secs));
+    pin!(grace);
+    loop {
+        tokio::select! {
+            _ = resumed.notified() => {
+                debug!("▶️ [hold_seat] 玩家已重连 - player_id: {}", player_id);
+                break;
+            }
+            // 到期后离开房间，随后收到自己的 Quit 时结束
+            _ = &mut grace, if !grace.is_elapsed() => {
+                if state
+                    .reconnecting
+                    .remove_if(&player_id, |_, pending| pending.connection_id == connection_id)
+                    .is_some()
+                {
+                    debug!(
+                        "⌛ [hold_seat] 重连宽限期结束，释放座位 - player_id: {}, room_id: {}",
+                        player_id, room_id
+                    );
+                    if let Err(e) = leave_room(&state, &room_id, player_id, None).await {
+                        error!("❌ [hold_seat] 离开房间失败 - 错误: {}", e);
+                        break;
+                    }
+                }
+            }
+            received = rx.recv() => match received.map(|event| event.message) {
+                // 宽限期结束、被踢出或房间关闭
+                Ok(MessageType::Quit(quit_player_id, ..)) if quit_player_id == player_id => {
+                    state
+                        .reconnecting
+                        .remove_if(&player_id, |_, pending| pending.connection_id == connection_id);
+                    break;
+                }
+                // 错过的消息在重连时从房间历史补发
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => {
+                    state
+                        .reconnecting
+                        .remove_if(&player_id, |_, pending| pending.connection_id == connection_id);
+                    break;
+                }
+            }
+        }
+    }
+}
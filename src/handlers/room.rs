@@ -1,50 +1,66 @@
 use axum::{extract::State, response::IntoResponse};
+use dashmap::mapref::entry::Entry;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use http::StatusCode;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
-use tokio::sync::broadcast;
 
-use super::handle_broadcast_to_ws;
-use super::handle_ws_to_broadcast;
 use crate::MessageType;
 use crate::QuitRoomRequest;
-use crate::{AppState, Car, Player, Room};
+use crate::{
+    AppState, AuthPlayer, Friend, Player, QuitReason, Room, RoomChannel, RoomHandle, RoomPhase,
+    RoomVisibility, announce_phase, hash_password, player_rating, sync_room, verify_password_hash,
+};
 use axum::Json;
 use tracing::debug;
 use tracing::error;
 
 #[derive(Deserialize)]
 pub struct CreateRoomRequest {
-    pub player_id: i32,
-    pub player_name: String,
     pub car_id: i32,
     pub weather_id: i32,
     pub background_id: i32,
+    // 房间最大人数，不指定时使用服务器上限
+    pub max_players: Option<usize>,
+    #[serde(default)]
+    pub visibility: RoomVisibility,
+    // 私密房间的密码，可省略（仅凭邀请码加入）
+    pub password: Option<String>,
 }
 pub async fn create_room(
     State(state): State<AppState>,
+    auth: AuthPlayer,
     Json(request): Json<CreateRoomRequest>,
 ) -> impl IntoResponse {
-    let room_id = request.player_id;
-    if state.inner.room_info.get(&room_id).is_none() {
-        let cars = vec![];
-        state.inner.room_info.insert(
-            room_id,
-            Room {
-                room_id,
-                players: vec![],
-                cars: cars,
-                weather_id: request.weather_id,
-                background_id: request.background_id,
-            },
-        );
-    } else {
-        return (StatusCode::BAD_REQUEST, "房间已存在").into_response();
+    let max_players = request
+        .max_players
+        .unwrap_or(state.room_config.max_players);
+    if max_players == 0 || max_players > state.room_config.max_players {
+        return (StatusCode::BAD_REQUEST, "房间人数超出限制").into_response();
     }
-    if state.inner.room_broadcast_couple.get(&room_id).is_none() {
-        let (tx, rx) = broadcast::channel(100);
-        state.inner.room_broadcast_couple.insert(room_id, (tx, rx));
+    if let Err(e) = check_catalog(&state, request.weather_id, request.background_id) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
     }
+    let password_hash = match request.password.as_deref().filter(|p| !p.is_empty()) {
+        Some(password) => match hash_password(password) {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                error!("❌ [create_room] 密码哈希失败 - 错误: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "房间创建失败").into_response();
+            }
+        },
+        None => None,
+    };
+    let room_id = open_room(
+        &state,
+        auth.player_id,
+        request.weather_id,
+        request.background_id,
+        max_players,
+        request.visibility,
+        password_hash,
+    );
 
     // 返回json
     let json = json!({
@@ -54,59 +70,491 @@ pub async fn create_room(
     (StatusCode::OK, Json(json)).into_response()
 }
 
+/// 创建房间并启动房间任务，返回房间码；/createroom 与快速匹配共用
+pub fn open_room(
+    state: &AppState,
+    owner_id: i32,
+    weather_id: i32,
+    background_id: i32,
+    max_players: usize,
+    visibility: RoomVisibility,
+    password_hash: Option<String>,
+) -> String {
+    // 生成未被占用的房间码，通过 entry 保证并发创建时不会覆盖已有房间
+    let room_id = loop {
+        let room_id = generate_room_id();
+        if let Entry::Vacant(entry) = state.rooms.entry(room_id.clone()) {
+            let room = Room {
+                room_id: room_id.clone(),
+                owner_id,
+                players: vec![],
+                cars: vec![],
+                weather_id,
+                background_id,
+                max_players,
+                car_seats: state.room_config.car_seats,
+                visibility,
+                password_hash: password_hash.clone(),
+                invite_codes: HashSet::new(),
+                spectators: vec![],
+                spectator_chat: true,
+                banned_ids: HashSet::new(),
+                phase: RoomPhase::Lobby,
+                phase_epoch: 0,
+                sessions: HashMap::new(),
+                settled_epoch: None,
+                closed: false,
+            };
+            let handle = RoomHandle::spawn(room, RoomChannel::new(state.room_config.history_len));
+            tokio::spawn(close_if_empty(state.clone(), handle.clone()));
+            entry.insert(handle);
+            break room_id;
+        }
+    };
+    state.notify_lobby();
+    room_id
+}
+
+// 创建后到期仍没有玩家加入的房间关闭，避免房间任务和大厅列表中的房间一直残留；
+// 有玩家加入过的房间在最后一名玩家离开时关闭
+async fn close_if_empty(state: AppState, handle: RoomHandle) {
+    tokio::time::sleep(Duration::from_secs(state.room_config.empty_room_ttl_secs)).await;
+    let closed = handle
+        .update(|room, channel| {
+            if !room.players.is_empty() {
+                return false;
+            }
+            dismiss_spectators(room, channel);
+            room.closed = true;
+            true
+        })
+        .await;
+    if closed == Some(true) {
+        debug!("⌛ [close_if_empty] 房间 {} 一直没有玩家加入，已关闭", handle.room_id());
+        close_room(&state, &handle);
+        state.notify_lobby();
+    }
+}
+
+// 房间码字符集，去掉了容易混淆的 0/O、1/I/L
+const ROOM_ID_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+const ROOM_ID_LEN: usize = 6;
+
+/// 生成随机的短房间码
+pub fn generate_room_id() -> String {
+    uuid::Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(ROOM_ID_LEN)
+        .map(|b| ROOM_ID_ALPHABET[*b as usize % ROOM_ID_ALPHABET.len()] as char)
+        .collect()
+}
+
+/// 统一房间码格式，客户端输入的房间码忽略首尾空白和大小写
+pub fn normalize_room_id(room_id: &str) -> String {
+    room_id.trim().to_uppercase()
+}
+
+/// 请求体中的房间码按 normalize_room_id 统一格式
+pub fn deserialize_room_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|room_id| normalize_room_id(&room_id))
+}
+
+/// 校验天气和背景是否在目录中
+pub fn check_catalog(
+    state: &AppState,
+    weather_id: i32,
+    background_id: i32,
+) -> Result<(), &'static str> {
+    if !state.catalog.has_weather(weather_id) {
+        return Err("天气不存在");
+    }
+    if !state.catalog.has_background(background_id) {
+        return Err("背景不存在");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChangeRoomSettingsRequest {
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
+    // 省略的字段保持不变
+    pub weather_id: Option<i32>,
+    pub background_id: Option<i32>,
+    pub spectator_chat: Option<bool>,
+}
+// 房主修改房间的天气、背景和观战者聊天开关
+pub async fn change_room_settings(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<ChangeRoomSettingsRequest>,
+) -> impl IntoResponse {
+    let Some(handle) = state.room(&request.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    let task_state = state.clone();
+    let result = handle
+        .update(move |room, channel| {
+            if !room.is_owner(auth.player_id) {
+                return Err((StatusCode::FORBIDDEN, "只有房主可以修改房间设置"));
+            }
+            if !room.phase.is_idle() {
+                return Err((StatusCode::CONFLICT, "比赛进行中"));
+            }
+            let weather_id = request.weather_id.unwrap_or(room.weather_id);
+            let background_id = request.background_id.unwrap_or(room.background_id);
+            check_catalog(&task_state, weather_id, background_id)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            room.weather_id = weather_id;
+            room.background_id = background_id;
+            if let Some(spectator_chat) = request.spectator_chat {
+                room.spectator_chat = spectator_chat;
+            }
+            if let Err(e) = channel.send(MessageType::Sync(Box::new(room.clone()))) {
+                error!("❌ [change_room_settings] 同步房间失败 - 错误: {}", e);
+            }
+            Ok(())
+        })
+        .await;
+    match result {
+        Some(Ok(())) => {}
+        Some(Err(e)) => return e.into_response(),
+        None => return (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
+    }
+    state.notify_lobby();
+    (StatusCode::OK, "房间设置修改成功").into_response()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateInviteRequest {
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
+}
+// 房主生成一次性邀请码
+pub async fn create_invite(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<CreateInviteRequest>,
+) -> impl IntoResponse {
+    let Some(handle) = state.room(&request.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    let invite_code = handle
+        .update(move |room, _| {
+            room.is_owner(auth.player_id).then(|| {
+                let invite_code = uuid::Uuid::new_v4().simple().to_string();
+                room.invite_codes.insert(invite_code.clone());
+                invite_code
+            })
+        })
+        .await;
+    let invite_code = match invite_code {
+        Some(Some(invite_code)) => invite_code,
+        Some(None) => return (StatusCode::FORBIDDEN, "只有房主可以生成邀请码").into_response(),
+        None => return (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
+    };
+    let json = json!({
+        "room_id": request.room_id,
+        "invite_code": invite_code,
+    });
+    (StatusCode::OK, Json(json)).into_response()
+}
+
+/// 检查玩家是否可以加入房间；凭邀请码加入时返回该邀请码，由加入房间时消耗
+pub async fn check_room_access(
+    state: &AppState,
+    room_id: &str,
+    player_id: i32,
+    password: Option<&str>,
+    invite_code: Option<&str>,
+) -> Result<Option<String>, (StatusCode, &'static str)> {
+    let Some(handle) = state.room(room_id) else {
+        return Err((StatusCode::BAD_REQUEST, "房间不存在"));
+    };
+    let room = handle.snapshot();
+    if room.banned_ids.contains(&player_id) {
+        return Err((StatusCode::FORBIDDEN, "已被房主禁止加入该房间"));
+    }
+    let (owner_id, visibility, password_hash) = (room.owner_id, room.visibility, room.password_hash);
+    if owner_id == player_id || visibility == RoomVisibility::Public {
+        return Ok(None);
+    }
+    if let Some(invite_code) = invite_code {
+        if room.invite_codes.contains(invite_code) {
+            return Ok(Some(invite_code.to_string()));
+        }
+        return Err((StatusCode::FORBIDDEN, "邀请码无效"));
+    }
+    match visibility {
+        RoomVisibility::Public => Ok(None),
+        RoomVisibility::Friends => {
+            match Friend::get_all_friends(&state.storage, &state.guests, owner_id).await {
+                Ok(friends) if friends.friend_ids.iter().any(|f| f.player_id == player_id) => Ok(None),
+                Ok(_) => Err((StatusCode::FORBIDDEN, "仅房主好友可以加入")),
+                Err(e) => {
+                    error!("❌ [check_room_access] 获取好友失败 - 错误: {}", e);
+                    Err((StatusCode::FORBIDDEN, "仅房主好友可以加入"))
+                }
+            }
+        }
+        RoomVisibility::Private => match (password_hash, password) {
+            (Some(hash), Some(password)) if verify_password_hash(&hash, password) => Ok(None),
+            (Some(_), Some(_)) => Err((StatusCode::FORBIDDEN, "房间密码错误")),
+            _ => Err((StatusCode::FORBIDDEN, "需要房间密码或邀请码")),
+        },
+    }
+}
+
 pub async fn quit_room(
     State(state): State<AppState>,
+    auth: AuthPlayer,
     Json(request): Json<QuitRoomRequest>,
 ) -> impl IntoResponse {
     let room_id = request.room_id;
-    let quit_player_id = request.player_id;
-    if (*state).room_info.get(&room_id).is_none() {
-        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    let quit_player_id = auth.player_id;
+    // Quit 携带退出原因，被移除玩家的连接收到后发送对应的关闭帧
+    match leave_room(&state, &room_id, quit_player_id, Some(QuitReason::UserQuit)).await {
+        Ok(_) => (StatusCode::OK, "房间退出成功").into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
     }
-    if state.inner.room_broadcast_couple.get(&room_id).is_none() {
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TakeSeatRequest {
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
+    pub car_id: i32,
+    pub skin_id: i32,
+}
+// 观战者在有空位时成为玩家，沿用当前的观战连接
+pub async fn take_seat(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<TakeSeatRequest>,
+) -> impl IntoResponse {
+    let player_id = auth.player_id;
+    let player = Player {
+        player_id,
+        player_name: auth.player_name.clone(),
+        car_id: request.car_id,
+        rating: player_rating(&state, auth.player_id, auth.guest).await,
+        guest: auth.guest,
+        ready: false,
+        reconnecting: false,
+    };
+    let Some(handle) = state.room(&request.room_id) else {
         return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    };
+    let result: Option<Result<(), &str>> = handle
+        .update(move |room, channel| {
+            room.promote_spectator(player, request.skin_id)?;
+            if room.claim_vacant_host(player_id)
+                && let Err(e) = channel.send(MessageType::HostChanged(room.room_id.clone(), player_id))
+            {
+                error!("❌ [take_seat] 房主变更广播失败 - 错误: {}", e);
+            }
+            if let Err(e) = channel.send(MessageType::Sync(Box::new(room.clone()))) {
+                error!("❌ [take_seat] 同步房间失败 - 错误: {}", e);
+            }
+            Ok(())
+        })
+        .await;
+    match result {
+        Some(Ok(())) => {}
+        Some(Err(e)) => return (StatusCode::CONFLICT, e).into_response(),
+        None => return (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
     }
-    let couple = match state.inner.room_broadcast_couple.get(&room_id) {
-        Some(couple) => couple,
-        None => {
-            return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
-        }
+    state.notify_lobby();
+    (StatusCode::OK, "已加入比赛").into_response()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KickPlayerRequest {
+    #[serde(deserialize_with = "crate::deserialize_room_id")]
+    pub room_id: String,
+    pub player_id: i32,
+}
+// 房主将玩家踢出房间，被踢玩家可以重新加入
+pub async fn kick_player(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<KickPlayerRequest>,
+) -> impl IntoResponse {
+    remove_by_host(&state, &auth, &request, QuitReason::Kicked).await
+}
+
+// 房主封禁玩家：在房间中则踢出，房间存在期间不能再加入
+pub async fn ban_player(
+    State(state): State<AppState>,
+    auth: AuthPlayer,
+    Json(request): Json<KickPlayerRequest>,
+) -> impl IntoResponse {
+    remove_by_host(&state, &auth, &request, QuitReason::Banned).await
+}
+
+async fn remove_by_host(
+    state: &AppState,
+    auth: &AuthPlayer,
+    request: &KickPlayerRequest,
+    reason: QuitReason,
+) -> axum::response::Response {
+    let Some(handle) = state.room(&request.room_id) else {
+        return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
     };
-    let mut room_info = match state.inner.room_info.get_mut(&room_id) {
-        Some(room) => room,
-        None => {
-            return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
+    let host_id = auth.player_id;
+    let target_id = request.player_id;
+    let task_state = state.clone();
+    // 检查权限和移出玩家在同一个命令中完成，期间房间不会被其他操作修改
+    let result = handle
+        .update(move |room, channel| {
+            if !room.is_owner(host_id) {
+                return Err((StatusCode::FORBIDDEN, "只有房主可以踢出玩家"));
+            }
+            if target_id == host_id {
+                return Err((StatusCode::BAD_REQUEST, "不能踢出自己"));
+            }
+            if reason == QuitReason::Banned {
+                room.banned_ids.insert(target_id);
+            }
+            // 观战者同样可以被踢出或封禁
+            if !room.has_member(target_id) {
+                return Ok(false);
+            }
+            debug!(
+                "👢 [remove_by_host] 房主 {} 将玩家 {} 移出房间 {} - 原因: {:?}",
+                host_id, target_id, room.room_id, reason
+            );
+            // Quit 携带原因，被移除玩家的连接收到后发送对应的关闭帧
+            depart(&task_state, room, channel, target_id, Some(reason))
+                .map(|_| true)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))
+        })
+        .await;
+    match result {
+        Some(Ok(true)) => {
+            state.notify_lobby();
+            (StatusCode::OK, "玩家已被移出房间").into_response()
         }
-    };
-    if let Some(pos) = room_info
-        .players
-        .iter()
-        .position(|p| p.player_id == quit_player_id)
-    {
-        // 直接取出被删除的玩家（拿到所有权，不用 clone）
-        let player = room_info.players.remove(pos);
+        Some(Ok(false)) => match reason {
+            QuitReason::Banned => (StatusCode::OK, "已禁止该玩家加入").into_response(),
+            _ => (StatusCode::BAD_REQUEST, "玩家不在房间中").into_response(),
+        },
+        Some(Err(e)) => e.into_response(),
+        None => (StatusCode::BAD_REQUEST, "房间不存在").into_response(),
+    }
+}
 
-        // 删除该玩家对应的 car
-        room_info.cars.retain(|c| c.car_id != player.car_id);
+/// 玩家所在的房间（占座或观战）
+pub fn current_room_of(state: &AppState, player_id: i32) -> Option<String> {
+    state
+        .rooms
+        .iter()
+        .find(|handle| handle.read(|room| room.has_member(player_id)))
+        .map(|handle| handle.room_id().to_string())
+}
 
-        // 从所有 car 的 player_ids 里移除这个玩家 id
-        for car in &mut room_info.cars {
-            car.player_ids.retain(|id| *id != quit_player_id);
+/// 新连接加入 joined_room_id 后：玩家离开所在的其他房间，旧的房间连接以 Replaced 关闭，保证同一玩家最多只在一个房间中
+pub async fn evict_player(state: &AppState, player_id: i32, joined_room_id: &str) {
+    let room_ids: Vec<String> = state
+        .rooms
+        .iter()
+        .filter(|handle| handle.room_id() != joined_room_id)
+        .filter(|handle| handle.read(|room| room.has_member(player_id)))
+        .map(|handle| handle.room_id().to_string())
+        .collect();
+    // 其他房间中掉线保留的座位一并释放
+    state
+        .reconnecting
+        .remove_if(&player_id, |_, pending| pending.room_id != joined_room_id);
+    for room_id in room_ids {
+        debug!(
+            "🔁 [evict_player] 玩家 {} 建立了新连接，离开房间 {}",
+            player_id, room_id
+        );
+        if let Err(e) = leave_room(state, &room_id, player_id, Some(QuitReason::Replaced)).await {
+            debug!("🛑 [evict_player] 玩家 {} 离开房间 {}: {}", player_id, room_id, e);
         }
-    } else {
-        return (StatusCode::BAD_REQUEST, "玩家不存在").into_response();
     }
-    (*state).normal_quit_room.insert(quit_player_id, ());
+}
+
+/// 玩家（或观战者）离开房间；reason 为服务器主动断开的原因，随 Quit 发给被移除玩家的连接
+pub async fn leave_room(
+    state: &AppState,
+    room_id: &str,
+    player_id: i32,
+    reason: Option<QuitReason>,
+) -> Result<(), &'static str> {
+    let handle = state.room(room_id).ok_or("房间不存在")?;
+    let task_state = state.clone();
+    let closed = handle
+        .update(move |room, channel| depart(&task_state, room, channel, player_id, reason))
+        .await
+        .ok_or("房间不存在")??;
+    if closed {
+        close_room(state, &handle);
+    }
+    state.notify_lobby();
+    Ok(())
+}
 
-    let tx = couple.0.clone();
-    match tx.send(MessageType::Quit(request.player_id, room_id)) {
+/// 在房间任务中移除成员并广播 Quit；房主离开时广播 HostChanged，随后广播阶段变化或房间快照。
+/// 最后一名玩家离开时房间关闭，剩余的观战者以 RoomClosed 断开，返回 true
+pub fn depart(
+    state: &AppState,
+    room: &mut Room,
+    channel: &RoomChannel,
+    player_id: i32,
+    reason: Option<QuitReason>,
+) -> Result<bool, &'static str> {
+    let departure = room.leave(player_id)?;
+    let room_id = room.room_id.clone();
+    match channel.send(MessageType::Quit(player_id, room_id.clone(), reason)) {
         Ok(_) => {
-            debug!("✅ [broadcast_to_ws] 退出消息广播成功");
+            debug!("✅ [leave_room] 退出消息广播成功 - player_id: {}", player_id);
         }
         Err(e) => {
-            error!("❌ [broadcast_to_ws] 退出消息广播失败 - 错误: {}", e);
-            return (StatusCode::BAD_REQUEST, "房间退出失败").into_response();
+            error!("❌ [leave_room] 退出消息广播失败 - 错误: {}", e);
+        }
+    }
+    if departure.closed {
+        dismiss_spectators(room, channel);
+        return Ok(true);
+    }
+    if let Some(new_owner) = departure.new_owner {
+        debug!(
+            "👑 [leave_room] 房主 {} 离开，房间 {} 移交给 {}",
+            player_id, room_id, new_owner
+        );
+        if let Err(e) = channel.send(MessageType::HostChanged(room_id, new_owner)) {
+            error!("❌ [leave_room] 房主变更广播失败 - 错误: {}", e);
         }
     }
-    (StatusCode::OK, "房间退出成功").into_response()
+    if departure.phase_changed {
+        announce_phase(state, channel, room);
+    } else {
+        sync_room(channel, room);
+    }
+    Ok(false)
+}
+
+// 房间关闭时剩余的观战者以 RoomClosed 断开
+fn dismiss_spectators(room: &mut Room, channel: &RoomChannel) {
+    for spectator in room.spectators.drain(..) {
+        let quit = MessageType::Quit(spectator.player_id, room.room_id.clone(), Some(QuitReason::RoomClosed));
+        if let Err(e) = channel.send(quit) {
+            error!("❌ [leave_room] 观战者退出广播失败 - 错误: {}", e);
+        }
+    }
+}
+
+/// 房间关闭后从房间表中移除；同一房间码已被新房间占用时保留新房间
+pub fn close_room(state: &AppState, handle: &RoomHandle) {
+    if state
+        .rooms
+        .remove_if(handle.room_id(), |_, current| current.same_room(handle))
+        .is_some()
+    {
+        debug!("🗑️ [leave_room] 最后一名玩家离开，房间 {} 已删除", handle.room_id());
+    }
 }
@@ -0,0 +1,54 @@
+use axum::{
+    extract::{
+        State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    response::IntoResponse,
+};
+use tracing::{debug, error};
+
+use crate::{AppState, AuthPlayer, ConnectionKind, SUPPORTED_SUBPROTOCOLS, WireFormat};
+
+// 玩家级会话连接：不依赖房间，主菜单等界面用来接收好友邀请等推送
+pub async fn session_websocket_handler(
+    ws: WebSocketUpgrade,
+    auth: AuthPlayer,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    debug!(
+        "🔌 [session_websocket_handler] 玩家 {} 建立会话连接",
+        auth.player_id
+    );
+    let ws = ws.protocols(SUPPORTED_SUBPROTOCOLS);
+    let format = WireFormat::from_subprotocol(ws.selected_protocol());
+    ws.on_upgrade(move |socket| handle_session_websocket(socket, auth.player_id, format, state))
+}
+
+async fn handle_session_websocket(
+    mut socket: WebSocket,
+    player_id: i32,
+    format: WireFormat,
+    state: AppState,
+) {
+    let (_connection, mut inbox) = state
+        .connections
+        .register(player_id, ConnectionKind::Session, None);
+    loop {
+        tokio::select! {
+            event = inbox.recv() => {
+                let Some(event) = event else { break };
+                if socket.send(format.encode(&event)).await.is_err() {
+                    error!("❌ [session_websocket] 消息发送失败 - player_id: {}", player_id);
+                    break;
+                }
+            }
+            // 会话连接只推送，不处理客户端消息（Ping 由 axum 自动回复）
+            message = socket.recv() => {
+                if let Some(Ok(Message::Close(_)) | Err(_)) | None = message {
+                    break;
+                }
+            }
+        }
+    }
+    debug!("🔌 [session_websocket] 玩家 {} 会话连接已关闭", player_id);
+}
@@ -15,409 +15,596 @@ use axum::{
     response::IntoResponse,
 };
 use bytes::Bytes;
-use futures::{SinkExt, StreamExt, future::join};
-use http::StatusCode;
+use futures::{SinkExt, StreamExt, stream::SplitSink};
+use http::{HeaderMap, StatusCode};
 use log::info;
-use serde_json::json;
 use tokio::{pin, sync::Mutex, time::sleep};
 use tracing::{debug, error};
 
-use crate::{AppState, MessageType, Player, Room};
-use crate::{Car, dto::MessageResponse};
+use crate::{
+    AppState, AuthPlayer, ClientMessage, ConnectionKind, ErrorCode, FrameError, MessageType, Player, SUPPORTED_SUBPROTOCOLS,
+    RoomEvent, ServerMessage, Spectator, WireFormat, Disconnect, DuplicateConnectionPolicy, QuitReason, RoomHandle,
+    check_room_access, current_room_of, depart, evict_player, claim_pending, disconnect, negotiate_protocol_version,
+    normalize_room_id, player_rating, sync_room,
+};
+use crate::dto::MessageResponse;
+
+// 私密房间密码优先从请求头读取；浏览器的 WebSocket 无法设置请求头，也可以通过 password 查询参数传递
+const ROOM_PASSWORD_HEADER: &str = "x-room-password";
+
+// 可以写入日志的 /ws 查询参数
+const LOGGED_PARAMS: [&str; 6] = ["room_id", "car_id", "skin_id", "spectate", "protocol_version", "last_seq"];
 
 // WebSocket处理函数
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
+    auth: AuthPlayer,
     Query(paramas): Query<HashMap<String, String>>,
+    headers: HeaderMap,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
+    // 查询参数中带有 token、password、invite_code、resume_token 等凭证，只记录非敏感参数
+    let logged: Vec<(&str, &str)> = LOGGED_PARAMS
+        .iter()
+        .filter_map(|key| paramas.get(*key).map(|value| (*key, value.as_str())))
+        .collect();
     debug!(
         "🔌 [websocket_handler] 收到 WebSocket 连接请求，参数: {:?}",
-        paramas
-    );
-
-    let Some(player_id) = paramas.get("player_id") else {
-        error!("❌ [websocket_handler] 缺少player_id参数");
-        return (StatusCode::BAD_REQUEST, "缺少room_id参数").into_response();
-    };
-    debug!(
-        "✅ [websocket_handler] 获取到 player_id 参数: {}",
-        player_id
+        logged
     );
 
-    let player_id = match player_id.parse::<i32>() {
-        Ok(player_id) => {
-            debug!("✅ [websocket_handler] player_id 解析成功: {}", player_id);
-            player_id
-        }
-        Err(_) => {
-            error!(
-                "❌ [websocket_handler] player_id参数格式错误: {}",
-                player_id
-            );
-            return (StatusCode::BAD_REQUEST, "player_id参数格式错误").into_response();
-        }
-    };
+    let player_id = auth.player_id;
+    let player_name = auth.player_name;
     let Some(room_id) = paramas.get("room_id") else {
         error!("❌ [websocket_handler] 缺少room_id参数");
         return (StatusCode::BAD_REQUEST, "缺少room_id参数").into_response();
     };
     debug!("✅ [websocket_handler] 获取到 room_id 参数: {}", room_id);
 
-    let room_id = match room_id.parse::<i32>() {
-        Ok(room_id) => {
-            debug!("✅ [websocket_handler] room_id 解析成功: {}", room_id);
-            room_id
-        }
-        Err(_) => {
-            error!("❌ [websocket_handler] room_id参数格式错误: {}", room_id);
-            return (StatusCode::BAD_REQUEST, "room_id参数格式错误").into_response();
+    let room_id = normalize_room_id(room_id);
+    // 观战者不占用座位，满员或比赛中也可以加入
+    let spectate = paramas
+        .get("spectate")
+        .is_some_and(|v| v == "true" || v == "1");
+    // 掉线重连时凭 Welcome 中的 resume_token 接回原座位，不需要重新选车
+    let resume_token = paramas.get("resume_token").cloned();
+    let policy = state.room_config.duplicate_connection;
+    // 同一玩家再次占座连接自己所在的房间（第二个标签页、掉线前重连）：新连接直接接管座位
+    let takeover = !spectate
+        && resume_token.is_none()
+        && policy == DuplicateConnectionPolicy::NewestWins
+        && state.room(&room_id).is_some_and(|handle| {
+            handle.read(|room| room.players.iter().any(|p| p.player_id == player_id))
+        });
+    if resume_token.is_none()
+        && policy == DuplicateConnectionPolicy::RejectNew
+        && let Some(current) = current_room_of(&state, player_id)
+    {
+        error!(
+            "❌ [websocket_handler] 玩家已在房间中 - player_id: {}, room_id: {}",
+            player_id, current
+        );
+        return (StatusCode::CONFLICT, "玩家已在房间中").into_response();
+    }
+    match state.room(&room_id).map(|handle| handle.snapshot()) {
+        Some(_) if spectate || takeover || resume_token.is_some() => {}
+        Some(room) if room.is_full() => {
+            error!("❌ [websocket_handler] 房间已满 - room_id: {}", room_id);
+            return (StatusCode::BAD_REQUEST, "房间已满").into_response();
         }
-    };
-    let Some(player_name) = paramas.get("player_name") else {
-        error!("❌ [websocket_handler] 缺少player_name参数");
-        return (StatusCode::BAD_REQUEST, "缺少player_name参数").into_response();
-    };
-    debug!(
-        "✅ [websocket_handler] 获取到 player_name 参数: {}",
-        player_name
-    );
-
-    let player_name = match player_name.parse::<String>() {
-        Ok(player_name) => {
-            debug!(
-                "✅ [websocket_handler] player_name 解析成功: {}",
-                player_name
-            );
-            player_name
+        Some(room) if !room.phase.is_idle() => {
+            error!("❌ [websocket_handler] 比赛进行中 - room_id: {}", room_id);
+            return (StatusCode::CONFLICT, "比赛进行中").into_response();
         }
-        Err(_) => {
-            error!(
-                "❌ [websocket_handler] player_name参数格式错误: {}",
-                player_name
-            );
-            return (StatusCode::BAD_REQUEST, "player_name参数格式错误").into_response();
+        Some(_) => {}
+        None => {
+            error!("❌ [websocket_handler] 房间不存在 - room_id: {}", room_id);
+            return (StatusCode::BAD_REQUEST, "房间不存在").into_response();
         }
+    }
+    let seat = if spectate || takeover || resume_token.is_some() {
+        None
+    } else {
+        let Some(car_id) = paramas.get("car_id") else {
+            error!("❌ [websocket_handler] 缺少car_id参数");
+            return (StatusCode::BAD_REQUEST, "缺少car_id参数").into_response();
+        };
+        debug!("✅ [websocket_handler] 获取到 car_id 参数: {}", car_id);
+
+        let car_id = match car_id.parse::<i32>() {
+            Ok(car_id) => {
+                debug!("✅ [websocket_handler] car_id 解析成功: {}", car_id);
+                car_id
+            }
+            Err(_) => {
+                error!("❌ [websocket_handler] car_id参数格式错误: {}", car_id);
+                return (StatusCode::BAD_REQUEST, "car_id参数格式错误").into_response();
+            }
+        };
+
+        let Some(skin_id) = paramas.get("skin_id") else {
+            error!("❌ [websocket_handler] 缺少skin_id参数");
+            return (StatusCode::BAD_REQUEST, "缺少skin_id参数").into_response();
+        };
+        debug!("✅ [websocket_handler] 获取到 skin_id 参数: {}", skin_id);
+        let skin_id = match skin_id.parse::<i32>() {
+            Ok(skin_id) => {
+                debug!("✅ [websocket_handler] skin_id 解析成功: {}", skin_id);
+                skin_id
+            }
+            Err(_) => {
+                error!("❌ [websocket_handler] skin_id参数格式错误: {}", skin_id);
+                return (StatusCode::BAD_REQUEST, "skin_id参数格式错误").into_response();
+            }
+        };
+        Some((car_id, skin_id))
     };
-    let Some(car_id) = paramas.get("car_id") else {
-        error!("❌ [websocket_handler] 缺少car_id参数");
-        return (StatusCode::BAD_REQUEST, "缺少car_id参数").into_response();
-    };
-    debug!("✅ [websocket_handler] 获取到 car_id 参数: {}", car_id);
 
-    let car_id = match car_id.parse::<i32>() {
-        Ok(car_id) => {
-            debug!("✅ [websocket_handler] car_id 解析成功: {}", car_id);
-            car_id
-        }
-        Err(_) => {
-            error!("❌ [websocket_handler] car_id参数格式错误: {}", car_id);
-            return (StatusCode::BAD_REQUEST, "car_id参数格式错误").into_response();
-        }
+    // 协商协议版本，未携带时使用当前版本
+    let requested_version = match paramas.get("protocol_version") {
+        Some(version) => match version.parse::<u32>() {
+            Ok(version) => Some(version),
+            Err(_) => {
+                error!(
+                    "❌ [websocket_handler] protocol_version参数格式错误: {}",
+                    version
+                );
+                return (StatusCode::BAD_REQUEST, "protocol_version参数格式错误").into_response();
+            }
+        },
+        None => None,
     };
-    let Some(weather_id) = paramas.get("weather_id") else {
-        error!("❌ [websocket_handler] 缺少weather_id参数");
-        return (StatusCode::BAD_REQUEST, "缺少weather_id参数").into_response();
+    let Some(protocol_version) = negotiate_protocol_version(requested_version) else {
+        error!(
+            "❌ [websocket_handler] 不支持的协议版本: {:?}",
+            requested_version
+        );
+        return (StatusCode::BAD_REQUEST, "不支持的协议版本").into_response();
     };
-    debug!(
-        "✅ [websocket_handler] 获取到 weather_id 参数: {}",
-        weather_id
-    );
 
-    let weather_id = match weather_id.parse::<i32>() {
-        Ok(weather_id) => {
-            debug!("✅ [websocket_handler] weather_id 解析成功: {}", weather_id);
-            weather_id
-        }
-        Err(_) => {
-            error!(
-                "❌ [websocket_handler] weather_id参数格式错误: {}",
-                weather_id
-            );
-            return (StatusCode::BAD_REQUEST, "weather_id参数格式错误").into_response();
-        }
-    };
-    let Some(background_id) = paramas.get("background_id") else {
-        error!("❌ [websocket_handler] 缺少background_id参数");
-        return (StatusCode::BAD_REQUEST, "缺少background_id参数").into_response();
+    // 重连时客户端最后收到的房间消息序号，省略时从掉线时的序号开始补发
+    let last_seq = match paramas.get("last_seq") {
+        Some(seq) => match seq.parse::<u64>() {
+            Ok(seq) => Some(seq),
+            Err(_) => {
+                error!("❌ [websocket_handler] last_seq参数格式错误: {}", seq);
+                return (StatusCode::BAD_REQUEST, "last_seq参数格式错误").into_response();
+            }
+        },
+        None => None,
     };
-    debug!(
-        "✅ [websocket_handler] 获取到 background_id 参数: {}",
-        background_id
-    );
 
-    let background_id = match background_id.parse::<i32>() {
-        Ok(background_id) => {
-            debug!(
-                "✅ [websocket_handler] background_id 解析成功: {}",
-                background_id
-            );
-            background_id
-        }
-        Err(_) => {
+    let mut invite_code = None;
+    if let Some(token) = &resume_token {
+        let resumable = state
+            .room(&room_id)
+            .is_some_and(|handle| handle.read(|room| room.can_resume(player_id, token)));
+        if !resumable {
             error!(
-                "❌ [websocket_handler] background_id参数格式错误: {}",
-                background_id
+                "❌ [websocket_handler] 重连凭证无效 - room_id: {}, player_id: {}",
+                room_id, player_id
             );
-            return (StatusCode::BAD_REQUEST, "background_id参数格式错误").into_response();
+            return (StatusCode::FORBIDDEN, "重连凭证无效或座位已释放").into_response();
         }
-    };
-
-    let Some(skin_id) = paramas.get("skin_id") else {
-        error!("❌ [websocket_handler] 缺少skin_id参数");
-        return (StatusCode::BAD_REQUEST, "缺少skin_id参数").into_response();
-    };
-    debug!("✅ [websocket_handler] 获取到 skin_id 参数: {}", skin_id);
-    let skin_id = match skin_id.parse::<i32>() {
-        Ok(skin_id) => {
-            debug!("✅ [websocket_handler] skin_id 解析成功: {}", skin_id);
-            skin_id
-        }
-        Err(_) => {
-            error!("❌ [websocket_handler] skin_id参数格式错误: {}", skin_id);
-            return (StatusCode::BAD_REQUEST, "skin_id参数格式错误").into_response();
+    // 接管座位的玩家已经在房间中；凭邀请码加入时邀请码在加入房间成功后才消耗
+    } else if !takeover {
+        let password = headers
+            .get(ROOM_PASSWORD_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| paramas.get("password").map(String::as_str));
+        match check_room_access(
+            &state,
+            &room_id,
+            player_id,
+            password,
+            paramas.get("invite_code").map(String::as_str),
+        )
+        .await
+        {
+            Ok(code) => invite_code = code,
+            Err(rejection) => {
+                error!(
+                    "❌ [websocket_handler] 无权加入房间 - room_id: {}, player_id: {}",
+                    room_id, player_id
+                );
+                return rejection.into_response();
+            }
         }
-    };
-
+    }
     debug!(
         "🚀 [websocket_handler] 所有参数验证成功，准备升级 WebSocket 连接 - player_id: {}, room_id: {}, player_name: {}",
         player_id, room_id, player_name
     );
 
-    ws.on_upgrade(move |socket| async move {
-        handle_websocket(
-            socket,
-            player_name,
-            car_id,
-            weather_id,
-            background_id,
-            player_id,
-            room_id,
+    let role = match (resume_token, seat) {
+        (Some(_), _) => JoinRole::Resume { last_seq },
+        (None, _) if takeover => JoinRole::Resume { last_seq },
+        (None, Some((car_id, skin_id))) => JoinRole::Player {
+            player: Player {
+                player_id,
+                player_name: player_name.clone(),
+                car_id,
+                rating: player_rating(&state, player_id, auth.guest).await,
+                guest: auth.guest,
+                ready: false,
+                reconnecting: false,
+            },
             skin_id,
-            state,
-        )
-        .await
+        },
+        (None, None) => JoinRole::Spectator(Spectator {
+            player_id,
+            player_name: player_name.clone(),
+        }),
+    };
+    // 通过 Sec-WebSocket-Protocol 选择序列化格式
+    let ws = ws.protocols(SUPPORTED_SUBPROTOCOLS);
+    let format = WireFormat::from_subprotocol(ws.selected_protocol());
+    debug!("✅ [websocket_handler] 序列化格式: {:?}", format);
+    ws.on_upgrade(move |socket| async move {
+        handle_websocket(socket, (role, invite_code), (player_id, player_name), room_id, protocol_version, format, state).await
     })
 }
 
+// 连接加入房间的身份
+enum JoinRole {
+    // 占用座位并带一辆车
+    Player { player: Player, skin_id: i32 },
+    // 只订阅房间广播
+    Spectator(Spectator),
+    // 掉线后接回保留的座位，补发 last_seq 之后的房间消息
+    Resume { last_seq: Option<u64> },
+}
+
 // 处理WebSocket连接
 async fn handle_websocket(
     mut socket: WebSocket,
-    player_name: String,
-    car_id: i32,
-    weather_id: i32,
-    background_id: i32,
-    player_id: i32,
-    room_id: i32,
-    skin_id: i32,
+    (role, invite_code): (JoinRole, Option<String>),
+    (player_id, player_name): (i32, String),
+    room_id: String,
+    protocol_version: u32,
+    format: WireFormat,
     state: AppState,
 ) {
     debug!(
         "🎯 [handle_websocket] 进入 WebSocket 处理函数 - player_id: {}, room_id: {}, player_name: {}",
         player_id, room_id, player_name
     );
-    let player = Player {
-        player_id,
-        player_name: player_name.clone(),
-        car_id,
-        weather_id,
-        background_id,
+    // 登记到全服连接表，连接结束时 connection 被 drop 自动注销
+    let (mut connection, inbox) =
+        state
+            .connections
+            .register(player_id, ConnectionKind::Room, Some(room_id.clone()));
+    // 获取房间任务的句柄
+    debug!(
+        "🔍 [handle_websocket] 正在获取房间 - room_id: {}",
+        room_id
+    );
+    let Some(handle) = state.room(&room_id) else {
+        error!("❌ [handle_websocket] 房间不存在 - room_id: {}", room_id);
+        return;
     };
-    let mut room_info = match state.inner.room_info.get_mut(&room_id) {
-        Some(room) => {
-            debug!(
-                "✅ [handle_websocket] 房间信息获取成功 - room_id: {}",
-                room_id
-            );
-            room
+    // 先取回保留记录，避免宽限期在接回座位的过程中到期
+    let resume_from = match role {
+        JoinRole::Resume { last_seq } => {
+            let held_seq = claim_pending(&state, &room_id, player_id);
+            last_seq.or(held_seq)
+        }
+        _ => None,
+    };
+    // 同一玩家最多只在一个房间中：按配置在加入成功后让旧连接离开原房间，或拒绝新连接
+    let policy = state.room_config.duplicate_connection;
+    let resuming = matches!(role, JoinRole::Resume { .. });
+    let duplicate = !resuming
+        && policy == DuplicateConnectionPolicy::RejectNew
+        && current_room_of(&state, player_id).is_some();
+    let connection_id = connection.connection_id();
+    let task_state = state.clone();
+    // 在房间任务中再次检查并加入：升级期间可能有其他玩家加入或比赛已开始
+    let joined = handle
+        .update(move |room_info, channel| {
+            let seated = room_info.players.iter().any(|p| p.player_id == player_id);
+            let rejection = match role {
+                _ if duplicate => Some("Already in a room"),
+                // 升级期间可能已被房主封禁
+                _ if room_info.banned_ids.contains(&player_id) => Some("Banned"),
+                // 改为观战会让出座位，最后一名玩家让出座位会关闭房间
+                JoinRole::Spectator(_) if seated && room_info.players.len() == 1 => {
+                    Some("Last player cannot spectate")
+                }
+                JoinRole::Spectator(_) => None,
+                // 宽限期已过或被房主踢出，座位已释放
+                JoinRole::Resume { .. } if !seated => Some("Seat expired"),
+                JoinRole::Resume { .. } => None,
+                JoinRole::Player { .. } if seated => Some("Already seated"),
+                JoinRole::Player { .. } if room_info.is_full() => Some("Room full"),
+                JoinRole::Player { .. } if !room_info.phase.is_idle() => Some("Race in progress"),
+                JoinRole::Player { ref player, .. } if !room_info.has_car_seat(player.car_id, player_id) => {
+                    Some("Car full")
+                }
+                JoinRole::Player { .. } => None,
+            };
+            if let Some(reason) = rejection {
+                return Err(reason);
+            }
+            // 其他检查都通过后才消耗邀请码，加入被拒绝时邀请码仍然有效
+            if let Some(code) = &invite_code
+                && !room_info.invite_codes.remove(code)
+            {
+                return Err("Invite code invalid");
+            }
+            match role {
+                JoinRole::Player { player, skin_id } => {
+                    debug!("📝 [handle_websocket] 添加玩家到房间");
+                    // 观战者改为占座，旧的观战连接随后以 Replaced 关闭
+                    room_info.remove_spectator(player_id);
+                    let car_id = player.car_id;
+                    room_info.players.push(player);
+                    debug!(
+                        "✅ [handle_websocket] 玩家添加成功，当前房间玩家数: {}",
+                        room_info.players.len()
+                    );
+
+                    // 已在上面检查过座位
+                    debug!("🚗 [handle_websocket] 玩家坐进车辆 {}", car_id);
+                    if let Err(e) = room_info.board_car(player_id, car_id, skin_id) {
+                        error!("❌ [handle_websocket] 坐进车辆失败 - 错误: {}", e);
+                    }
+                    debug!(
+                        "✅ [handle_websocket] 车辆添加成功，当前房间车辆数: {}",
+                        room_info.cars.len()
+                    );
+                    if room_info.claim_vacant_host(player_id) {
+                        debug!("👑 [handle_websocket] 房主不在座位上，玩家 {} 接任房主", player_id);
+                        if let Err(e) = channel.send(MessageType::HostChanged(room_info.room_id.clone(), player_id)) {
+                            error!("❌ [handle_websocket] 房主变更广播失败 - 错误: {}", e);
+                        }
+                    }
+                }
+                JoinRole::Spectator(spectator) => {
+                    debug!("👀 [handle_websocket] 添加观战者到房间");
+                    // 玩家改为观战：让出座位，旧的占座连接收到 Quit 后以 Replaced 关闭
+                    if seated
+                        && let Err(e) = depart(&task_state, room_info, channel, player_id, Some(QuitReason::Replaced))
+                    {
+                        error!("❌ [handle_websocket] 让出座位失败 - 错误: {}", e);
+                    }
+                    room_info.remove_spectator(player_id);
+                    room_info.spectators.push(spectator);
+                }
+                JoinRole::Resume { .. } => {
+                    debug!("🔁 [handle_websocket] 玩家重连，接回座位");
+                    room_info.set_reconnecting(player_id, false);
+                }
+            }
+            // 接管座位时关闭之前持有座位的连接
+            let replaced = room_info
+                .sessions
+                .get(&player_id)
+                .map(|session| session.connection_id)
+                .filter(|id| *id != connection_id);
+            let resume_token = room_info.attach_session(player_id, connection_id);
+            // Welcome 中的房间状态与该序号对应
+            let welcome_seq = channel.last_seq();
+            let welcome = ServerMessage::Welcome {
+                protocol_version,
+                room_info: room_info.clone(),
+                seq: welcome_seq,
+                resume_token: Some(resume_token),
+            };
+            // 新成员通过补发收到这条同步
+            sync_room(channel, room_info);
+            Ok((welcome, welcome_seq, replaced))
+        })
+        .await;
+    let (first_json, welcome_seq, replaced) = match joined {
+        Some(Ok(joined)) => joined,
+        Some(Err(reason)) => {
+            error!("❌ [handle_websocket] 无法加入房间 - room_id: {}, 原因: {}", room_id, reason);
+            let close_frame = Message::Close(Some(axum::extract::ws::CloseFrame {
+                code: 1008,
+                reason: reason.into(),
+            }));
+            if socket.send(close_frame).await.is_err() {
+                error!("❌ [handle_websocket] 关闭帧发送失败");
+            }
+            return;
         }
         None => {
             error!("❌ [handle_websocket] 房间不存在 - room_id: {}", room_id);
             return;
         }
     };
-    let first_json = {
-        debug!(
-            "🔍 [handle_websocket] 正在获取房间信息 - room_id: {}",
-            room_id
-        );
-        debug!("📝 [handle_websocket] 添加玩家到房间");
-        room_info.players.push(player.clone());
-        debug!(
-            "✅ [handle_websocket] 玩家添加成功，当前房间玩家数: {}",
-            room_info.players.len()
-        );
-
-        debug!("🚗 [handle_websocket] 添加车辆到房间");
-        room_info.cars.push(Car {
-            car_id,
-            skin_id,
-            player_ids: vec![player_id],
-        });
+    // 加入成功后再离开其他房间，加入被拒绝时保留原来的座位
+    if !resuming && policy == DuplicateConnectionPolicy::NewestWins {
+        evict_player(&state, player_id, &room_id).await;
+    }
+    if let Some(old_connection) = replaced {
         debug!(
-            "✅ [handle_websocket] 车辆添加成功，当前房间车辆数: {}",
-            room_info.cars.len()
+            "🔁 [handle_websocket] 新连接接管座位，关闭旧连接 - player_id: {}, connection_id: {}",
+            player_id, old_connection
         );
-
-        // 克隆数据用于返回，然后锁会在这个作用域结束时释放
-        json!({
-            "room_info": room_info.clone(),
-        })
-    };
+        state
+            .connections
+            .close(player_id, old_connection, QuitReason::Replaced);
+    }
+    state.notify_lobby();
     debug!(
-        "📤 [handle_websocket] 准备发送欢迎消息，房间信息: {:?}",
-        first_json
+        "📤 [handle_websocket] 准备发送欢迎消息 - room_id: {}, seq: {}",
+        room_id, welcome_seq
     );
 
-    if socket
-        .send(Message::Text(first_json.to_string().into()))
-        .await
-        .is_err()
-    {
+    if socket.send(format.encode(&first_json)).await.is_err() {
         error!("❌ [handle_websocket] 发送欢迎消息失败");
+        disconnect(&state, &room_id, player_id, connection_id, Disconnect::Lost).await;
         return;
     }
     debug!("✅ [handle_websocket] 欢迎消息发送成功");
-    // 获取广播通道
-    debug!(
-        "🔍 [handle_websocket] 正在获取广播通道 - room_id: {}",
-        room_id
-    );
-    let room = match state.inner.room_broadcast_couple.get(&room_id) {
-        Some(room) => {
-            debug!(
-                "✅ [handle_websocket] 广播通道获取成功 - room_id: {}",
-                room_id
-            );
-            room
-        }
-        None => {
-            error!(
-                "❌ [handle_websocket] 房间广播pipeline不存在 - room_id: {}",
-                room_id
-            );
+    // 补发掉线期间错过的聊天消息，房间状态以 Welcome 为准
+    let missed = resume_from
+        .and_then(|seq| handle.channel().replay_after(seq))
+        .unwrap_or_default();
+    for message in missed_chat(&missed, welcome_seq) {
+        if socket.send(format.encode(&message)).await.is_err() {
+            error!("❌ [handle_websocket] 补发消息失败");
+            disconnect(&state, &room_id, player_id, connection_id, Disconnect::Lost).await;
             return;
         }
-    };
-    let tx = room.0.clone();
+    }
 
     // 分离WebSocket发送和接收
     debug!("✂️ [handle_websocket] 分离 WebSocket 发送和接收通道");
     let (ws_sink, ws_stream) = socket.split();
-    let arc_ws_sink = Arc::new(Mutex::new(ws_sink));
+    let ws_sender = WsSender::new(ws_sink, format);
 
     let content = format!("{}登录了房间", player_name);
     debug!("📢 [handle_websocket] 准备广播登录消息: {}", content);
 
-    let heart_timeout_notify = Arc::new(AtomicBool::new(false));
+    let heartbeat = Arc::new(Heartbeat::new());
 
     // 群发信息 - 启动接收任务
     let ws_to_broadcast = tokio::spawn(handle_ws_to_broadcast(
         ws_stream,
-        tx.clone(),
-        room_id,
+        ws_sender.clone(),
+        handle.clone(),
         player_id,
-        heart_timeout_notify.clone(),
-        state.clone(),
+        heartbeat.clone(),
     ));
 
     // 监听broadcast pipeline如果收到消息则发送给客户端 - 启动发送任务
     let broadcast_to_ws = tokio::spawn(handle_broadcast_to_ws(
-        arc_ws_sink.clone(),
-        tx.clone(),
-        player,
-        content,
-        state.clone(),
+        ws_sender.clone(),
+        handle.clone(),
+        inbox,
+        (player_id, welcome_seq),
     ));
-    let room_info_clone = room_info.clone();
-    drop(room_info);
 
-    let heartbeat_task = tokio::spawn(heartbeat_task(
-        arc_ws_sink,
-        player_id,
-        heart_timeout_notify.clone(),
-        state.clone(),
-    ));
-    match tx.send(MessageType::Sync(room_info_clone)) {
-        Ok(_) => {
-            debug!("✅ [broadcast_to_ws] 登录消息广播成功");
+    let heartbeat_task = tokio::spawn(heartbeat_task(ws_sender.clone(), heartbeat));
+
+    // 接收任务结束即连接结束，据此决定离开房间还是保留座位等待重连
+    debug!("⏳ [handle_websocket] 等待任务结束...");
+    let mut ws_to_broadcast = ws_to_broadcast;
+    let mut broadcast_to_ws = broadcast_to_ws;
+    let how = tokio::select! {
+        joined = &mut ws_to_broadcast => match joined {
+            Ok(how) => Some(how),
+            Err(e) => {
+                error!(
+                    "❌ [handle_websocket] ws_to_broadcast 任务失败 - 错误: {}",
+                    e
+                );
+                Some(Disconnect::Lost)
+            }
+        },
+        // 同一玩家的新连接接管了座位，旧连接不再影响房间
+        reason = connection.closed() => {
+            debug!("🔁 [handle_websocket] 连接被取代 - player_id: {}", player_id);
+            let close_frame = Message::Close(Some(axum::extract::ws::CloseFrame {
+                code: reason.close_code(),
+                reason: reason.close_reason().into(),
+            }));
+            if ws_sender.send_raw(close_frame).await.is_err() {
+                error!("❌ [handle_websocket] 关闭帧发送失败");
+            }
+            ws_to_broadcast.abort();
+            None
         }
-        Err(e) => {
-            error!("❌ [broadcast_to_ws] 发送登录消息失败 - 错误: {}", e);
+        // 玩家已被移出房间或房间已关闭，关闭帧已发出；不等待客户端完成关闭握手
+        _ = &mut broadcast_to_ws => {
+            debug!("🛑 [handle_websocket] 已离开房间，结束连接 - player_id: {}", player_id);
+            ws_to_broadcast.abort();
+            Some(Disconnect::Closed)
         }
     };
+    broadcast_to_ws.abort();
+    heartbeat_task.abort();
+    if let Some(how) = how {
+        disconnect(&state, &room_id, player_id, connection_id, how).await;
+    }
+    debug!("room_id :{room_id} player_id :{player_id}");
+    // 房间的移除由 leave_room 在最后一名玩家离开时完成
+    debug!("👋 [handle_websocket] WebSocket 连接处理完成");
+}
 
-    // 等待任一任务结束
-    debug!("⏳ [handle_websocket] 等待任务结束...");
-    drop(room);
-    match tokio::join!(ws_to_broadcast, broadcast_to_ws, heartbeat_task) {
-        (Ok(_), Ok(_), Ok(_)) => {
-            debug!("🛑 [handle_websocket] 所有任务已结束");
-            debug!("room_id :{room_id} player_id :{player_id}");
-        }
-        (Err(e), _, _) => {
-            error!(
-                "❌ [handle_websocket] ws_to_broadcast 任务失败 - 错误: {}",
-                e
-            );
+/// 解析客户端发送的 JSON 文本帧，发送者以连接的认证身份为准
+pub fn parse_client_message(text: &str, player_id: i32) -> Result<MessageType, FrameError> {
+    let message: ClientMessage =
+        serde_json::from_str(text).map_err(|e| FrameError::Malformed(e.to_string()))?;
+    stamp_client_message(message, player_id)
+}
+
+/// 将客户端消息转换为房间广播消息，发送者以连接的认证身份为准
+pub fn stamp_client_message(message: ClientMessage, player_id: i32) -> Result<MessageType, FrameError> {
+    match message {
+        ClientMessage::Text {
+            content,
+            player_id: claimed,
+        } => {
+            check_sender(claimed, player_id)?;
+            Ok(MessageType::Text(MessageResponse { player_id, content }))
         }
-        (_, _, Err(e)) => {
-            error!(
-                "❌ [handle_websocket] broadcast_to_ws 任务失败 - 错误: {}",
-                e
-            );
+        ClientMessage::Emoji {
+            content,
+            player_id: claimed,
+        } => {
+            check_sender(claimed, player_id)?;
+            Ok(MessageType::Emoji(MessageResponse { player_id, content }))
         }
-        (_, Err(e), _) => {
-            error!(
-                "❌ [handle_websocket] heartbeat_task 任务失败 - 错误: {}",
-                e
-            );
+        ClientMessage::Replay { .. } => Err(FrameError::Malformed("replay 帧不能广播".to_string())),
+    }
+}
+
+// player_id 字段可省略，若携带则必须与连接身份一致
+fn check_sender(claimed: Option<i32>, player_id: i32) -> Result<(), FrameError> {
+    match claimed {
+        Some(claimed_id) if claimed_id != player_id => Err(FrameError::SenderMismatch(claimed_id)),
+        _ => Ok(()),
+    }
+}
+
+// 单个连接的发送端，序列化格式在握手时确定
+#[derive(Clone)]
+pub struct WsSender {
+    sink: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    format: WireFormat,
+}
+
+impl WsSender {
+    pub fn new(sink: SplitSink<WebSocket, Message>, format: WireFormat) -> Self {
+        Self {
+            sink: Arc::new(Mutex::new(sink)),
+            format,
         }
     }
-    debug!("room_id :{room_id} player_id :{player_id}");
-    // 清理：从房间中移除玩家
-    if room_id == player_id {
-        match (*state).room_broadcast_couple.remove(&room_id) {
-            Some(couple) => {
-                info!("couple removed");
-                drop(couple);
-            }
-            None => {
-                error!("❌ [handle_websocket] 房间广播管道不存在");
-                return;
-            }
-        };
-        match (*state).room_info.remove(&room_id) {
-            Some(_) => {
-                info!("room removed")
-            }
-            None => {
-                error!("❌ [handle_websocket] 房间不存在");
-                return;
-            }
-        };
-        debug!("🗑️ [handle_websocket] 房间 {} 已清空并删除", room_id);
+
+    pub fn format(&self) -> WireFormat {
+        self.format
+    }
+
+    /// 按连接的格式序列化并发送
+    pub async fn send(&self, message: &ServerMessage) -> Result<(), axum::Error> {
+        self.send_raw(self.format.encode(message)).await
+    }
+
+    /// 发送控制帧（Ping / Close）
+    pub async fn send_raw(&self, message: Message) -> Result<(), axum::Error> {
+        self.sink.lock().await.send(message).await
     }
-    (*state).normal_quit_room.remove(&player_id);
-    (*state).last_pong.remove(&player_id);
-    debug!("👋 [handle_websocket] WebSocket 连接处理完成");
 }
 
 /// 处理从 WebSocket 接收的消息并广播到房间
 pub async fn handle_ws_to_broadcast(
     mut ws_stream: futures::stream::SplitStream<WebSocket>,
-    tx: tokio::sync::broadcast::Sender<MessageType>,
-    room_id: i32,
+    ws_sink: WsSender,
+    room: RoomHandle,
     player_id: i32,
-    heart_timeout_notify: Arc<AtomicBool>,
-    state: AppState,
-) {
+    heartbeat: Arc<Heartbeat>,
+) -> Disconnect {
     debug!("🚀 [ws_to_broadcast] 启动 WebSocket 接收任务");
-    let heart_timeout_notify_clone = heart_timeout_notify.clone();
+    let heartbeat_clone = heartbeat.clone();
     let listen_heartbeat = tokio::spawn(async move {
         loop {
             sleep(Duration::from_secs(10)).await;
-            if heart_timeout_notify_clone.load(Ordering::Relaxed) {
+            if heartbeat_clone.is_timed_out() {
                 debug!("💔 [ws_to_broadcast] 心跳超时，通知客户端关闭连接");
                 break;
             }
@@ -427,354 +614,177 @@ pub async fn handle_ws_to_broadcast(
     // 🆕 Pin 住 JoinHandle
     pin!(listen_heartbeat);
     // 文本帧使用 json 交互
-    loop {
+    let how = loop {
         tokio::select! {
             _ = &mut listen_heartbeat => {
-                if room_id == player_id {
-                    let player_ids: Vec<i32> = {
-                        let room_info = match (*state).room_info.get(&room_id) {
-                            Some(room) => room,
-                            None => {
-                                error!("❌ [ws_to_broadcast] 房间不存在");
-                                break;
-                            }
-                        };
-
-                        room_info.players.iter().map(|p| p.player_id).collect()
-                    };
-
-                    for pid in player_ids {
-                        if pid != player_id {
-                            (*state).normal_quit_room.insert(pid, ());
-                        }
-                        match tx.send(MessageType::Quit(pid, room_id)) {
-                            Ok(_) => {
-                                debug!("✅ [ws_to_broadcast] 退出消息广播成功 - player_id: {}", pid);
-                            }
-                            Err(e) => {
-                                error!("❌ [ws_to_broadcast] 退出消息广播失败: 错误: {e}");
-                            }
-                        }
-                    }
-                } else {
-                    match tx.send(MessageType::Quit(player_id, room_id)) {
-                        Ok(_) => {
-                            debug!(
-                                "✅ [ws_to_broadcast] 退出消息广播成功 - player_id: {}",
-                                player_id
-                            );
-                        }
-                        Err(e) => {
-                            error!("❌ [ws_to_broadcast] 退出消息广播失败: 错误: {e}");
-                        }
-                    }
-                }
-                break;
+                break Disconnect::Lost;
             }
             Some(Ok(msg)) = ws_stream.next() => {
                 // debug!("📨 [ws_to_broadcast] 收到 WebSocket 消息: {:?}", msg);
                 match msg {
-                    Message::Text(text) => {
-                        debug!("📝 [ws_to_broadcast] 收到文本消息: {}", text);
-
-                        let json: serde_json::Value = match serde_json::from_str(&text) {
-                            Ok(json) => {
-                                debug!("✅ [ws_to_broadcast] JSON 解析成功: {:?}", json);
-                                json
-                            }
-                            Err(e) => {
-                                error!("❌ [ws_to_broadcast] JSON 解析失败: {} - 错误: {}", text, e);
+                    Message::Text(_) | Message::Binary(_) => {
+                        debug!("📝 [ws_to_broadcast] 收到数据帧: {:?}", msg);
+                        let message = match ws_sink.format().decode(&msg) {
+                            // 客户端发现序号缺口后请求补发，只回复给自己
+                            Ok(ClientMessage::Replay { after_seq }) => {
+                                debug!("🔁 [ws_to_broadcast] 客户端请求补发 - after_seq: {}", after_seq);
+                                for message in replay_messages(&room, after_seq).await {
+                                    if let Err(e) = ws_sink.send(&message).await {
+                                        error!("❌ [ws_to_broadcast] 补发消息失败 - 错误: {}", e);
+                                        break;
+                                    }
+                                }
                                 continue;
                             }
+                            decoded => decoded.and_then(|message| stamp_client_message(message, player_id)),
                         };
-
-                        let player_id = match json["player_id"].as_i64() {
-                            Some(player_id) => {
-                                let id = player_id as i32;
-                                debug!("✅ [ws_to_broadcast] 提取 player_id: {}", id);
-                                id
-                            }
-                            None => {
-                                error!("❌ [ws_to_broadcast] player_id字段不存在: {}", text);
+                        let message = match message {
+                            Ok(message) => message,
+                            Err(frame_error) => {
+                                error!(
+                                    "❌ [ws_to_broadcast] 客户端帧无法处理 - player_id: {}, 错误: {:?}",
+                                    player_id, frame_error
+                                );
+                                if let Err(e) = ws_sink.send(&frame_error.to_server_message()).await {
+                                    error!("❌ [ws_to_broadcast] 错误帧发送失败 - 错误: {}", e);
+                                }
                                 continue;
                             }
                         };
-
-                        let content = match json["content"].as_str() {
-                            Some(content) => {
-                                debug!("✅ [ws_to_broadcast] 提取 content: {}", content);
-                                content.to_string()
-                            }
-                            None => {
-                                error!("❌ [ws_to_broadcast] content字段不存在: {}", text);
-                                continue;
+                        let (member, chat_disabled) = room.read(|room| {
+                            (room.has_member(player_id), room.is_spectator(player_id) && !room.spectator_chat)
+                        });
+                        // 已被移出房间（踢出、封禁等）的连接在关闭前发来的消息直接丢弃
+                        if !member {
+                            debug!("🚫 [ws_to_broadcast] 玩家已不在房间中，丢弃消息 - player_id: {}", player_id);
+                            continue;
+                        }
+                        // 房主关闭观战者聊天后拒绝观战者的消息
+                        if chat_disabled {
+                            let error = ServerMessage::error(
+                                ErrorCode::SpectatorChatDisabled,
+                                "房主已关闭观战者聊天",
+                            );
+                            if let Err(e) = ws_sink.send(&error).await {
+                                error!("❌ [ws_to_broadcast] 错误帧发送失败 - 错误: {}", e);
                             }
-                        };
-
-                        let mes_type = match json["mes_type"].as_str() {
-                            Some(mes_type) => {
-                                debug!("✅ [ws_to_broadcast] 提取 type: {}", mes_type);
-                                mes_type.to_string()
+                            continue;
+                        }
+                        match room.channel().send(message) {
+                            Ok(_) => {
+                                debug!("✅ [ws_to_broadcast] 消息广播成功");
                             }
-                            None => {
-                                error!("❌ [ws_to_broadcast] type字段不存在: {}", text);
+                            Err(e) => {
+                                error!("❌ [ws_to_broadcast] 消息广播失败 - 错误: {}", e);
                                 continue;
                             }
                         };
-                        if mes_type == "text" {
-                            match tx.send(MessageType::Text(MessageResponse {
-                                player_id,
-                                content: content.clone(),
-                            })) {
-                                Ok(_) => {
-                                    debug!("✅ [ws_to_broadcast] 消息广播成功");
-                                }
-                                Err(e) => {
-                                    error!("❌ [ws_to_broadcast] 消息广播失败: {} - 错误: {}", text, e);
-                                    continue;
-                                }
-                            };
-                        } else if mes_type == "emoji" {
-                            match tx.send(MessageType::Emoji(MessageResponse { player_id, content })) {
-                                Ok(_) => {
-                                    debug!("✅ [ws_to_broadcast] 消息广播成功");
-                                }
-                                Err(e) => {
-                                    error!("❌ [ws_to_broadcast] 消息广播失败: {} - 错误: {}", text, e);
-                                    continue;
-                                }
-                            };
-                        }
                     }
                     Message::Close(close_frame) => {
                         debug!("📨 [ws_to_broadcast] 收到关闭消息: {:?}", close_frame);
-                        if room_id == player_id {
-                            let player_ids: Vec<i32> = {
-                                let room_info = match (*state).room_info.get(&room_id) {
-                                    Some(room) => room,
-                                    None => {
-                                        error!("❌ [ws_to_broadcast] 房间不存在");
-                                        continue;
-                                    }
-                                };
-
-                                room_info.players.iter().map(|p| p.player_id).collect()
-                            };
-
-                            for pid in player_ids {
-                                if pid != player_id {
-                                    (*state).normal_quit_room.insert(pid, ());
-                                }
-                                match tx.send(MessageType::Quit(pid, room_id)) {
-                                    Ok(_) => {
-                                        debug!(
-                                            "✅ [ws_to_broadcast] 退出消息广播成功 - player_id: {}",
-                                            pid
-                                        );
-                                    }
-                                    Err(e) => {
-                                        error!("❌ [ws_to_broadcast] 退出消息广播失败: 错误: {e}");
-                                    }
-                                }
-                            }
-                        } else {
-                            match tx.send(MessageType::Quit(player_id, room_id)) {
-                                Ok(_) => {
-                                    debug!(
-                                        "✅ [ws_to_broadcast] 退出消息广播成功 - player_id: {}",
-                                        player_id
-                                    );
-                                }
-                                Err(e) => {
-                                    error!("❌ [ws_to_broadcast] 退出消息广播失败: 错误: {e}");
-                                }
-                            }
-                        }
-                        break;
+                        break Disconnect::Closed;
                     }
-                    Message::Binary(binary) => {
-                        debug!("📨 [ws_to_broadcast] 收到二进制消息: {:?}", binary);
-                        continue;
-                    }
-                    Message::Ping(ping) => {
+                    Message::Ping(_ping) => {
                         // debug!("📨 [ws_to_broadcast] 收到 Ping 消息: {:?}", ping);
                         continue;
                     }
-                    Message::Pong(pong) => {
-                        (*state).last_pong.insert(player_id, Instant::now());
+                    Message::Pong(_pong) => {
+                        heartbeat.pong();
                         // debug!("📨 [ws_to_broadcast] 收到 Pong 消息: {:?}", pong);
                         continue;
                     }
-                    _ => {
-                        debug!("📨 [ws_to_broadcast] 收到未知消息: {:?}", msg);
-                        continue;
-                    }
                 };
             }
         }
-    }
+    };
     debug!("🛑 [ws_to_broadcast] WebSocket 接收任务结束");
+    how
 }
 
 /// 处理从广播通道接收的消息并发送到 WebSocket
 pub async fn handle_broadcast_to_ws(
-    mut ws_sink: Arc<Mutex<futures::stream::SplitSink<WebSocket, Message>>>,
-    tx: tokio::sync::broadcast::Sender<MessageType>,
-    player: Player,
-    content: String,
-    state: AppState,
+    ws_sink: WsSender,
+    room: RoomHandle,
+    mut inbox: tokio::sync::mpsc::Receiver<ServerMessage>,
+    (player_id, welcome_seq): (i32, u64),
 ) {
     debug!("🚀 [broadcast_to_ws] 启动广播监听任务");
 
-    // 通知所有用户同步状态
-    debug!(
-        "📢 [broadcast_to_ws] 准备发送登录通知 - player_id: {}, content: {}",
-        player.player_id, content
-    );
-
     debug!("🔄 [broadcast_to_ws] 开始订阅广播频道");
-    let mut rx = tx.subscribe();
+    let mut rx = room.channel().subscribe();
+    // 已发送给客户端的最大序号；先补发 Welcome 之后、订阅之前的消息
+    let mut last_seq = welcome_seq;
+    send_replay(&ws_sink, &room, &mut last_seq).await;
 
     loop {
         debug!("⏳ [broadcast_to_ws] 等待接收广播消息...");
-        match rx.recv().await {
-            Ok(data) => {
-                match data {
-                    MessageType::Text(MessageResponse { player_id, content }) => {
-                        debug!(
-                            "📨 [broadcast_to_ws] 收到广播消息: player_id={}, content={}",
-                            player_id, content
-                        );
-                        let json_msg = json!({
-                            "type": "text",
-                            "player_id": player_id,
-                            "content": content,
-                        });
-                        debug!(
-                            "📤 [broadcast_to_ws] 准备发送消息到 WebSocket: {:?}",
-                            json_msg
-                        );
-
-                        if let Err(e) = (*ws_sink)
-                            .lock()
-                            .await
-                            .send(Message::Text(json_msg.to_string().into()))
-                            .await
-                        {
-                            error!("❌ [broadcast_to_ws] WebSocket 发送消息失败 - 错误: {}", e);
-                        } else {
-                            debug!("✅ [broadcast_to_ws] 消息发送成功");
-                        }
-                    }
-                    MessageType::Emoji(MessageResponse { player_id, content }) => {
-                        let json_msg = json!({
-                            "type": "emoji",
-                            "player_id": player_id,
-                            "content": content,
-                        });
-                        debug!(
-                            "📤 [broadcast_to_ws] 准备发送消息到 WebSocket: {:?}",
-                            json_msg
-                        );
-                        if let Err(e) = ws_sink
-                            .lock()
-                            .await
-                            .send(Message::Text(json_msg.to_string().into()))
-                            .await
-                        {
-                            error!("❌ [broadcast_to_ws] WebSocket 发送消息失败 - 错误: {}", e);
-                        } else {
-                            debug!("✅ [broadcast_to_ws] 消息发送成功");
-                        }
-                    }
-                    MessageType::Sync(room_info) => {
-                        debug!("同步状态");
-                        let json_msg = json!({
-                            "type": "sync",
-                            "room_info": room_info,
-                        });
+        let received = tokio::select! {
+            received = rx.recv() => received,
+            // 通过连接表推送给该玩家的个人消息（好友邀请等）
+            Some(event) = inbox.recv() => {
+                if let Err(e) = ws_sink.send(&event).await {
+                    error!("❌ [broadcast_to_ws] 个人消息发送失败 - 错误: {}", e);
+                }
+                continue;
+            }
+        };
+        match received {
+            Ok(event) => {
+                // 补发过的消息不再重复发送；Quit 不占用序号
+                let quit = matches!(event.message, MessageType::Quit(..));
+                if event.seq <= last_seq && !quit {
+                    continue;
+                }
+                last_seq = last_seq.max(event.seq);
+                let server_message = ServerMessage::from_broadcast(&event);
+                match event.message {
+                    MessageType::Text(_)
+                    | MessageType::Emoji(_)
+                    | MessageType::Sync(_)
+                    | MessageType::HostChanged(_, _)
+                    | MessageType::PhaseChanged(_, _, _) => {
+                        let Some(server_message) = server_message else {
+                            continue;
+                        };
                         debug!(
                             "📤 [broadcast_to_ws] 准备发送消息到 WebSocket: {:?}",
-                            json_msg
+                            server_message
                         );
-                        if let Err(e) = ws_sink
-                            .lock()
-                            .await
-                            .send(Message::Text(json_msg.to_string().into()))
-                            .await
-                        {
+                        if let Err(e) = ws_sink.send(&server_message).await {
                             error!("❌ [broadcast_to_ws] WebSocket 发送消息失败 - 错误: {}", e);
                         } else {
                             debug!("✅ [broadcast_to_ws] 消息发送成功");
                         }
-                        drop(room_info);
                     }
-                    MessageType::Quit(quit_player_id, room_id) => {
+                    MessageType::Quit(quit_player_id, room_id, reason) => {
                         debug!("🛑 [broadcast_to_ws] 收到退出消息");
                         debug!(
                             "quit_player_id :{quit_player_id} palyer_id :{},room_id :{room_id}",
-                            player.player_id
+                            player_id
                         );
-                        if quit_player_id == player.player_id {
-                            debug!("🛑 [broadcast_to_ws] 自己退出房间");
-                            let room_info = match state.inner.room_info.get(&room_id) {
-                                Some(room) => room,
-                                None => {
-                                    error!("❌ [broadcast_to_ws] 房间不存在");
-                                    debug!("✅ [broadcast_to_ws] 同步消息广播成功");
-                                    if (*state).normal_quit_room.get(&quit_player_id).is_some() {
-                                        let close_frame =
-                                            Message::Close(Some(axum::extract::ws::CloseFrame {
-                                                code: 1000, // 正常关闭
-                                                reason: "User quit".into(),
-                                            }));
-                                        match ws_sink.lock().await.send(close_frame).await {
-                                            Ok(_) => {
-                                                info!("✅ [broadcast_to_ws] 关闭帧发送成功");
-                                            }
-                                            Err(e) => {
-                                                error!(
-                                                    "❌ [broadcast_to_ws] quit_player_id :{quit_player_id} 关闭帧发送失败: 错误: {e}"
-                                                );
-                                            }
-                                        }
-                                    }
-                                    break;
-                                }
-                            };
-
-                            let room_info_clone = room_info.clone();
-                            drop(room_info);
-                            match tx.send(MessageType::Sync(room_info_clone)) {
+                        if quit_player_id != player_id {
+                            debug!("🛑 [broadcast_to_ws] 其他玩家退出房间");
+                            continue;
+                        }
+                        debug!("🛑 [broadcast_to_ws] 自己退出房间");
+                        // 服务器主动移出（退出、踢出、封禁、房间关闭、被取代）时发送对应的关闭帧
+                        if let Some(reason) = reason {
+                            let close_frame = Message::Close(Some(axum::extract::ws::CloseFrame {
+                                code: reason.close_code(),
+                                reason: reason.close_reason().into(),
+                            }));
+                            match ws_sink.send_raw(close_frame).await {
                                 Ok(_) => {
-                                    debug!("✅ [broadcast_to_ws] 同步消息广播成功");
-                                    if (*state).normal_quit_room.get(&quit_player_id).is_some() {
-                                        let close_frame =
-                                            Message::Close(Some(axum::extract::ws::CloseFrame {
-                                                code: 1000, // 正常关闭
-                                                reason: "User quit".into(),
-                                            }));
-                                        match ws_sink.lock().await.send(close_frame).await {
-                                            Ok(_) => {
-                                                info!("✅ [broadcast_to_ws] 关闭帧发送成功");
-                                            }
-                                            Err(e) => {
-                                                error!(
-                                                    "❌ [broadcast_to_ws] quit_player_id :{quit_player_id} 关闭帧发送失败: 错误: {e}"
-                                                );
-                                            }
-                                        }
-                                    }
+                                    info!("✅ [broadcast_to_ws] 关闭帧发送成功");
                                 }
                                 Err(e) => {
-                                    error!("❌ [broadcast_to_ws] 同步消息广播失败 - 错误: {}", e);
+                                    error!(
+                                        "❌ [broadcast_to_ws] quit_player_id :{quit_player_id} 关闭帧发送失败: 错误: {e}"
+                                    );
                                 }
-                            };
-                            break;
-                        } else {
-                            debug!("🛑 [broadcast_to_ws] 其他玩家退出房间");
-                            continue;
+                            }
                         }
+                        break;
                     }
                 };
             }
@@ -786,11 +796,15 @@ pub async fn handle_broadcast_to_ws(
                         code: 1008,
                         reason: "inactivetimeout".into(),
                     }));
-                    if ws_sink.lock().await.send(close_frame).await.is_err() {
+                    if ws_sink.send_raw(close_frame).await.is_err() {
                         error!("❌ [broadcast_to_ws] 关闭帧发送失败");
                     }
                     break;
                 }
+                // 落后太多被广播通道跳过的消息从房间历史补发
+                if let tokio::sync::broadcast::error::RecvError::Lagged(_) = e {
+                    send_replay(&ws_sink, &room, &mut last_seq).await;
+                }
             }
         }
     }
@@ -798,48 +812,107 @@ pub async fn handle_broadcast_to_ws(
     debug!("🛑 [broadcast_to_ws] 广播监听任务结束");
 }
 
+/// 重连时 Welcome 之后补发的消息：只补发序号不大于 welcome_seq 的 text / emoji。
+/// 旧的 sync 等状态消息已包含在 Welcome 的房间状态中，补发会让客户端状态回退；
+/// 序号大于 welcome_seq 的消息由广播任务正常下发
+pub fn missed_chat(events: &[RoomEvent], welcome_seq: u64) -> Vec<ServerMessage> {
+    events
+        .iter()
+        .filter(|event| event.seq <= welcome_seq)
+        .filter_map(ServerMessage::from_broadcast)
+        .filter(|message| matches!(message, ServerMessage::Text { .. } | ServerMessage::Emoji { .. }))
+        .collect()
+}
+
+/// 序号大于 after_seq 的房间消息，历史中已没有需要的消息时改为当前房间状态的 Sync
+pub async fn replay_messages(room: &RoomHandle, after_seq: u64) -> Vec<ServerMessage> {
+    if let Some(events) = room.channel().replay_after(after_seq) {
+        return events.iter().filter_map(ServerMessage::from_broadcast).collect();
+    }
+    // 在房间任务中读取，保证房间状态与序号对应
+    room.update(|room, channel| ServerMessage::Sync {
+        seq: channel.last_seq(),
+        room_info: room.clone(),
+    })
+    .await
+    .into_iter()
+    .collect()
+}
+
+// 补发 last_seq 之后的房间消息并推进 last_seq
+async fn send_replay(ws_sink: &WsSender, room: &RoomHandle, last_seq: &mut u64) {
+    for message in replay_messages(room, *last_seq).await {
+        if let Err(e) = ws_sink.send(&message).await {
+            error!("❌ [broadcast_to_ws] 补发消息失败 - 错误: {}", e);
+            return;
+        }
+        *last_seq = message.seq().map_or(*last_seq, |seq| seq.max(*last_seq));
+    }
+}
+
+// 单个连接的心跳状态：接收任务记录 Pong，心跳任务据此判断超时
+pub struct Heartbeat {
+    last_pong: std::sync::Mutex<Instant>,
+    timed_out: AtomicBool,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            last_pong: std::sync::Mutex::new(Instant::now()),
+            timed_out: AtomicBool::new(false),
+        }
+    }
+
+    pub fn pong(&self) {
+        *self.last_pong.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    pub fn since_last_pong(&self) -> Duration {
+        self.last_pong.lock().unwrap_or_else(|e| e.into_inner()).elapsed()
+    }
+
+    pub fn time_out(&self) {
+        self.timed_out.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // 心跳任务
-async fn heartbeat_task(
-    ws_sink: Arc<Mutex<futures::stream::SplitSink<WebSocket, Message>>>,
-    player_id: i32,
-    heart_timeout_notify: Arc<AtomicBool>,
-    state: AppState,
-) {
+async fn heartbeat_task(ws_sink: WsSender, heartbeat: Arc<Heartbeat>) {
     let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
 
     loop {
         interval.tick().await;
 
         // 检查上次收到 Pong 的时间
-        let last_pong = (*state)
-            .last_pong
-            .entry(player_id)
-            .or_insert(Instant::now());
-        let elapsed = last_pong.elapsed();
+        let elapsed = heartbeat.since_last_pong();
 
         if elapsed > tokio::time::Duration::from_secs(10) {
-            // 90秒内没收到 Pong，认为连接已死
-            error!("💔 [heartbeat] 90秒内未收到 Pong，连接可能已断开");
-            heart_timeout_notify.store(true, Ordering::Relaxed);
-            // (*state).last_pong.remove(&player_id);
-            // drop(last_pong);
+            // 10秒内没收到 Pong，认为连接已死
+            error!("💔 [heartbeat] 10秒内未收到 Pong，连接可能已断开");
+            heartbeat.time_out();
             break;
         }
 
         // debug!("💓 [heartbeat] 发送 Ping (上次 Pong: {:?}秒前)", elapsed.as_secs());
 
         if let Err(e) = ws_sink
-            .lock()
-            .await
-            .send(Message::Ping(Bytes::from_static(b"ping")))
+            .send_raw(Message::Ping(Bytes::from_static(b"ping")))
             .await
         {
             error!("❌ [heartbeat] Ping 发送失败: {}", e);
-            heart_timeout_notify.store(true, Ordering::Relaxed);
-            // (*state).last_pong.remove(&player_id);
-            // drop(last_pong);
+            heartbeat.time_out();
             break;
         }
-        drop(last_pong);
     }
 }
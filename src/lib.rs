@@ -8,15 +8,13 @@
  */
 use anyhow::Result;
 use dashmap::DashMap;
-use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
-use tracing::info;
-use tracing::error;
 use std::sync::Arc;
 use std::ops::Deref;
-use std::time::Instant;
+use tokio::sync::watch;
 pub mod config;
 pub use config::*;
+pub mod auth;
+pub use auth::*;
 pub mod handlers;
 pub use handlers::*;
 pub mod dto;
@@ -25,6 +23,14 @@ pub mod types;
 pub use types::*;
 pub mod models;
 pub use models::*;
+// handlers 和 models 都有公开的 friend 模块，根路径下的 friend 指向 models::friend
+pub use models::friend;
+pub mod registry;
+pub use registry::*;
+pub mod actor;
+pub use actor::*;
+pub mod storage;
+pub use storage::*;
 
 use axum::{
     Router,
@@ -59,14 +65,35 @@ pub fn get_route(state: AppState) -> Router {
     
     Router::new()
         .route("/ws", get(handlers::websocket_handler))
+        .route("/ws/lobby", get(lobby_websocket_handler))
+        .route("/ws/session", get(session_websocket_handler))
+        .route("/rooms", get(get_rooms))
+        .route("/ws/match", get(match_websocket_handler))
+        .route("/cancelmatch", post(cancel_match))
+        .route("/reportresult", post(report_result))
+        .route("/setready", post(set_ready))
+        .route("/startrace", post(start_race))
         .route("/createroom", post(create_room))
         .route("/quitroom", post(quit_room))
+        .route("/createinvite", post(create_invite))
+        .route("/takeseat", post(take_seat))
+        .route("/invitefriend", post(invite_friend))
+        .route("/respondinvite", post(respond_invite))
+        .route("/getinvites", post(get_invites))
+        .route("/kickplayer", post(kick_player))
+        .route("/banplayer", post(ban_player))
+        .route("/changeroomsettings", post(change_room_settings))
         .route("/changecar",post(change_car))
         .route("/changecarskin",post(change_car_skin))
         .route("/addfriend",post(add_friend))
         .route("/removefriend",post(remove_friend))
         .route("/getfriends",post(get_friends))
-        .route("/addplayer",post(add_player))
+        .route("/register",post(register))
+        .route("/login",post(login))
+        .route("/changepassword",post(change_password))
+        .route("/resetpassword",post(reset_password))
+        .route("/guest",post(guest_login))
+        .route("/upgradeguest",post(upgrade_guest))
         .layer(cors)
         .with_state(state)
 }
@@ -79,7 +106,12 @@ impl AppState {
     pub fn try_new(config: &Config) -> Result<Self> {
         Ok(AppState {
             inner: Arc::new(InnerAppState::new(
-            &config.database,
+            Storage::try_new(config.storage, &config.database)?,
+            JwtKeys::try_new(&config.key)?,
+            config.room.clone(),
+            config.matchmaking.clone(),
+            config.catalog.clone(),
+            config.guest.clone(),
             )),
         })
     }
@@ -97,37 +129,68 @@ impl Deref for AppState {
 #[derive(Clone)]
 // 服务器状态
 pub struct InnerAppState {
-    // 活跃会话
-    pub room_broadcast_couple: Arc<DashMap<i32, room_broadcast_couple>>,
-    pub room_info: Arc<DashMap<i32, Room>>,
-    pub normal_quit_room: Arc<DashMap<i32, ()>>, // 正常退出房间
-    pub last_pong:Arc<DashMap<i32, Instant>>,
-    pub pool: PgPool,
-    // // 用于数据数据解密
-    // pub public_key: Vec<u8>,
-    // // 用于数据加密
-    // pub private_key: Vec<u8>,
+    // 活跃房间，每个房间由独立的房间任务持有状态
+    pub rooms: Arc<DashMap<String, RoomHandle>>,
+    pub guests: Arc<DashMap<i32, Guest>>, // 游客身份
+    pub guest_config: GuestConfig,
+    // 玩家、好友、分数等持久化数据
+    pub storage: Storage,
+    // 用于签发和校验 token
+    pub jwt: Arc<JwtKeys>,
+    pub room_config: RoomConfig,
+    // 快速匹配队列
+    pub match_queue: Arc<DashMap<i32, MatchTicket>>,
+    pub match_config: MatchConfig,
+    // 天气 / 背景目录
+    pub catalog: CatalogConfig,
+    // 全服在线连接表，用于向任意在线玩家推送消息
+    pub connections: Arc<ConnectionRegistry>,
+    // 待处理的好友房间邀请
+    pub room_invites: Arc<DashMap<String, RoomInvite>>,
+    // 房间列表变化通知，大厅连接订阅后重新推送
+    pub lobby_notify: Arc<watch::Sender<()>>,
+    // 掉线后等待重连的玩家
+    pub reconnecting: Arc<DashMap<i32, PendingResume>>,
 }
 
 impl InnerAppState {
-    pub(crate) fn new(url: &str) -> Self {
-        // 创建一个pgsql连接池
-        let pool = match PgPool::connect_lazy(url) {
-            Ok(pool) => {
-                info!("Postgres connection pool created successfully");
-                pool
-            }
-            Err(e) => {
-                error!("Failed to create Postgres connection pool: {}", e);
-                panic!("Failed to create Postgres connection pool");
-            }
-        };
+    pub(crate) fn new(
+        storage: Storage,
+        jwt: JwtKeys,
+        room_config: RoomConfig,
+        match_config: MatchConfig,
+        catalog: CatalogConfig,
+        guest_config: GuestConfig,
+    ) -> Self {
         InnerAppState {
-            room_broadcast_couple: Arc::new(DashMap::new()),
-            room_info: Arc::new(DashMap::new()),
-            normal_quit_room: Arc::new(DashMap::new()),
-            last_pong: Arc::new(DashMap::new()),
-            pool,
+            rooms: Arc::new(DashMap::new()),
+            guests: Arc::new(DashMap::new()),
+            guest_config,
+            storage,
+            jwt: Arc::new(jwt),
+            room_config,
+            match_queue: Arc::new(DashMap::new()),
+            match_config,
+            catalog,
+            connections: Arc::new(ConnectionRegistry::default()),
+            room_invites: Arc::new(DashMap::new()),
+            lobby_notify: Arc::new(watch::Sender::new(())),
+            reconnecting: Arc::new(DashMap::new()),
         }
     }
+
+    /// 查找房间任务的句柄
+    pub fn room(&self, room_id: &str) -> Option<RoomHandle> {
+        self.rooms.get(room_id).map(|handle| handle.clone())
+    }
+
+    /// 推送消息到玩家打开的所有连接，返回是否有连接收到
+    pub fn notify_player(&self, player_id: i32, message: ServerMessage) -> bool {
+        self.connections.send(player_id, &message) > 0
+    }
+
+    /// 通知大厅房间列表发生变化
+    pub fn notify_lobby(&self) {
+        self.lobby_notify.send_replace(());
+    }
 }
@@ -1,7 +1,6 @@
 use anyhow::Result;
 use minigame::*;
 use tracing::level_filters::LevelFilter;
-use tracing_appender::rolling;
 use tracing_subscriber::{fmt::Layer, prelude::*};
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -35,6 +34,7 @@ async fn main() -> Result<()> {
         }
     };
 
+    tokio::spawn(purge_guests(state.clone()));
     let app = get_route(state);
 
     // get ip and port from config
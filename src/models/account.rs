@@ -0,0 +1,97 @@
+use anyhow::Result;
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::Storage;
+
+// 带登录凭证的玩家账号
+#[derive(sqlx::FromRow, Debug, Clone, Deserialize, Serialize)]
+pub struct Account {
+    pub player_id: i32,
+    pub username: String,
+    pub player_name: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub is_admin: bool,
+    // 每次修改或重置密码时加一，签发时写入 token，旧版本的 token 随之失效
+    #[serde(skip_serializing)]
+    pub token_version: i32,
+}
+
+impl Account {
+    /// 注册账号，返回服务器分配的 player_id
+    pub async fn register(
+        storage: &Storage,
+        username: &str,
+        player_name: &str,
+        password: &str,
+    ) -> Result<i32> {
+        let password_hash = hash_password(password)?;
+        storage
+            .players
+            .create_account(None, username, player_name, &password_hash)
+            .await
+    }
+
+    /// 游客升级为正式账号，沿用游客的 player_id（同样来自 player_id_seq）
+    pub async fn register_with_id(
+        storage: &Storage,
+        player_id: i32,
+        username: &str,
+        player_name: &str,
+        password: &str,
+    ) -> Result<()> {
+        let password_hash = hash_password(password)?;
+        storage
+            .players
+            .create_account(Some(player_id), username, player_name, &password_hash)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_by_username(storage: &Storage, username: &str) -> Result<Self> {
+        let account = storage.players.account_by_username(username).await?;
+        account.ok_or_else(|| anyhow::anyhow!("账号不存在"))
+    }
+
+    pub async fn get(storage: &Storage, player_id: i32) -> Result<Self> {
+        let account = storage.players.account(player_id).await?;
+        account.ok_or_else(|| anyhow::anyhow!("账号不存在"))
+    }
+
+    /// 修改密码，此前签发的 token 全部失效
+    pub async fn set_password(storage: &Storage, player_id: i32, password: &str) -> Result<()> {
+        let password_hash = hash_password(password)?;
+        if !storage.players.set_password_hash(player_id, &password_hash).await? {
+            return Err(anyhow::anyhow!("账号不存在"));
+        }
+        Ok(())
+    }
+
+    /// 校验明文密码是否与账号的哈希匹配
+    pub fn verify_password(&self, password: &str) -> bool {
+        verify_password_hash(&self.password_hash, password)
+    }
+}
+
+/// 校验明文密码是否与 argon2 哈希匹配
+pub fn verify_password_hash(password_hash: &str, password: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// 使用 argon2 和随机盐生成密码哈希
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("密码哈希失败: {}", e))?;
+    Ok(hash.to_string())
+}
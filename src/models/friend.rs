@@ -1,8 +1,8 @@
 use anyhow::Result;
+use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Postgres};
 
-use crate::SimplePlayer;
+use crate::{Guest, SimplePlayer, Storage};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Friend {
@@ -18,54 +18,52 @@ impl Friend {
         }
     }
 
-    pub async fn add_friend(pool: &Pool<Postgres>, master_id: i32, friend_id: i32) -> Result<()> {
-        // 双向添加好友
-        let result1 =
-            sqlx::query("INSERT INTO friend_mapping (master_id, friend_id) VALUES ($1, $2)")
-                .bind(master_id)
-                .bind(friend_id)
-                .execute(pool)
-                .await?;
-        
-        let result2 =
-            sqlx::query("INSERT INTO friend_mapping (master_id,friend_id) VALUES ($1, $2)")
-                .bind(friend_id)
-                .bind(master_id)
-                .execute(pool)
-                .await?;
-        if result1.rows_affected() == 0 && result2.rows_affected() == 0 {
-            return Err(anyhow::anyhow!("添加好友失败"));
-        }
-        Ok(())
+    pub async fn add_friend(storage: &Storage, master_id: i32, friend_id: i32) -> Result<()> {
+        storage.friends.add_friend(master_id, friend_id).await
     }
 
-    pub async fn remove_friend(
-        pool: &Pool<Postgres>,
-        master_id: i32,
-        friend_id: i32,
-    ) -> Result<()> {
-        let result =
-            sqlx::query(r#"DELETE FROM friend_mapping WHERE master_id = $1 AND friend_id = $2"#)
-                .bind(master_id)
-                .bind(friend_id)
-                .execute(pool)
-                .await?;
-        if result.rows_affected() == 0 {
+    pub async fn remove_friend(storage: &Storage, master_id: i32, friend_id: i32) -> Result<()> {
+        if !storage.friends.remove_friend(master_id, friend_id).await? {
             return Err(anyhow::anyhow!("删除好友失败"));
         }
         Ok(())
     }
 
-    pub async fn get_friends(pool: &Pool<Postgres>, master_id: i32) -> Result<Self> {
-        let friend_ids: Vec<SimplePlayer> = sqlx::query_as("SELECT player_id, player_name FROM player_info WHERE player_id IN (SELECT friend_id FROM friend_mapping WHERE master_id = $1)")
-            .bind(master_id)
-            .fetch_all(pool)
-            .await?;
-
+    pub async fn get_friends(storage: &Storage, master_id: i32) -> Result<Self> {
+        let friend_ids = storage.friends.friends_of(master_id).await?;
         Ok(Self {
             master_id,
             friend_ids,
         })
     }
 
+    /// 获取好友列表，包含保存在内存中的游客好友关系
+    pub async fn get_all_friends(
+        storage: &Storage,
+        guests: &DashMap<i32, Guest>,
+        master_id: i32,
+    ) -> Result<Self> {
+        let friend_ids = match guests.get(&master_id).map(|guest| guest.friend_ids.clone()) {
+            Some(ids) => {
+                let mut friend_ids = vec![];
+                for id in ids {
+                    if let Some(guest) = guests.get(&id) {
+                        friend_ids.push(SimplePlayer::new(id, guest.player_name.clone()));
+                    } else if let Ok(player) = SimplePlayer::get_player(storage, id).await {
+                        friend_ids.push(player);
+                    }
+                }
+                friend_ids
+            }
+            None => {
+                let mut friend_ids = Self::get_friends(storage, master_id).await?.friend_ids;
+                friend_ids.extend(Guest::guest_friends_of(guests, master_id));
+                friend_ids
+            }
+        };
+        Ok(Self {
+            master_id,
+            friend_ids,
+        })
+    }
 }
@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{SimplePlayer, Storage};
+
+// 游客身份，只保存在内存中，升级为正式账号后移除
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Guest {
+    pub player_id: i32,
+    pub player_name: String,
+    pub expires_at: i64,
+    // 与游客相关的好友关系无法写入 friend_mapping（外键约束），先保存在内存中
+    pub friend_ids: HashSet<i32>,
+}
+
+impl Guest {
+    pub fn new(player_id: i32, player_name: String, expires_at: i64) -> Self {
+        Self {
+            player_id,
+            player_name,
+            expires_at,
+            friend_ids: HashSet::new(),
+        }
+    }
+
+    /// 从玩家 id 序列中分配游客 id，升级为正式账号时沿用同一个 id
+    pub async fn allocate_id(storage: &Storage) -> Result<i32> {
+        storage.players.allocate_id().await
+    }
+
+    /// 清理已过期的游客
+    pub fn purge_expired(guests: &DashMap<i32, Guest>) {
+        let now = chrono::Utc::now().timestamp();
+        guests.retain(|_, guest| guest.expires_at > now);
+    }
+
+    /// 添加好友关系（至少一方为游客）
+    pub fn add_friend(guests: &DashMap<i32, Guest>, master_id: i32, friend_id: i32) {
+        if let Some(mut guest) = guests.get_mut(&master_id) {
+            guest.friend_ids.insert(friend_id);
+        }
+        if let Some(mut guest) = guests.get_mut(&friend_id) {
+            guest.friend_ids.insert(master_id);
+        }
+    }
+
+    /// 删除好友关系，返回是否存在该关系
+    pub fn remove_friend(guests: &DashMap<i32, Guest>, master_id: i32, friend_id: i32) -> bool {
+        let mut removed = false;
+        if let Some(mut guest) = guests.get_mut(&master_id) {
+            removed |= guest.friend_ids.remove(&friend_id);
+        }
+        if let Some(mut guest) = guests.get_mut(&friend_id) {
+            removed |= guest.friend_ids.remove(&master_id);
+        }
+        removed
+    }
+
+    /// 与该玩家互为好友的游客
+    pub fn guest_friends_of(guests: &DashMap<i32, Guest>, player_id: i32) -> Vec<SimplePlayer> {
+        guests
+            .iter()
+            .filter(|guest| guest.friend_ids.contains(&player_id))
+            .map(|guest| SimplePlayer::new(guest.player_id, guest.player_name.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,48 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::ServerMessage;
+
+// 好友房间邀请，只保存在内存中，过期后清理
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoomInvite {
+    pub invite_id: String,
+    pub room_id: String,
+    pub from_id: i32,
+    pub from_name: String,
+    pub to_id: i32,
+    pub expires_at: i64,
+}
+
+impl RoomInvite {
+    pub fn new(room_id: String, from_id: i32, from_name: String, to_id: i32, ttl_secs: i64) -> Self {
+        Self {
+            invite_id: uuid::Uuid::new_v4().simple().to_string(),
+            room_id,
+            from_id,
+            from_name,
+            to_id,
+            expires_at: chrono::Utc::now().timestamp() + ttl_secs,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= chrono::Utc::now().timestamp()
+    }
+
+    /// 清理已过期的邀请
+    pub fn purge_expired(invites: &DashMap<String, RoomInvite>) {
+        invites.retain(|_, invite| !invite.is_expired());
+    }
+
+    /// 推送给被邀请玩家的消息
+    pub fn to_server_message(&self) -> ServerMessage {
+        ServerMessage::RoomInvite {
+            invite_id: self.invite_id.clone(),
+            room_id: self.room_id.clone(),
+            from_id: self.from_id,
+            from_name: self.from_name.clone(),
+            expires_at: self.expires_at,
+        }
+    }
+}
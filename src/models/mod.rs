@@ -2,3 +2,11 @@ pub mod friend;
 pub use friend::*;
 pub mod player;
 pub use player::*;
+pub mod account;
+pub use account::*;
+pub mod guest;
+pub use guest::*;
+pub mod rating;
+pub use rating::*;
+pub mod invite;
+pub use invite::*;
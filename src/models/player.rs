@@ -1,6 +1,7 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Postgres};
+
+use crate::Storage;
 
 #[derive(sqlx::FromRow, Debug, Clone, Deserialize, Serialize)]
 pub struct SimplePlayer {
@@ -16,20 +17,8 @@ impl SimplePlayer {
         }
     }
 
-    pub async fn add_player(
-        pool: &Pool<Postgres>,
-        player_id: i32,
-        player_name: String,
-    ) -> Result<()> {
-        let result =
-            sqlx::query("INSERT INTO player_info (player_id, player_name) VALUES ($1, $2)")
-                .bind(player_id)
-                .bind(player_name)
-                .execute(pool)
-                .await?;
-        if result.rows_affected() == 0 {
-            return Err(anyhow::anyhow!("添加玩家失败"));
-        }
-        Ok(())
+    pub async fn get_player(storage: &Storage, player_id: i32) -> Result<Self> {
+        let player = storage.players.player(player_id).await?;
+        player.ok_or_else(|| anyhow::anyhow!("玩家不存在"))
     }
 }
@@ -0,0 +1,57 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::Storage;
+
+// 新玩家和游客的初始分
+pub const DEFAULT_RATING: i32 = 1500;
+// 单局最大变动幅度，多人对局按对手数量均分
+const K_FACTOR: f64 = 32.0;
+
+#[derive(sqlx::FromRow, Debug, Clone, Deserialize, Serialize)]
+pub struct Rating {
+    pub player_id: i32,
+    pub rating: i32,
+    pub games_played: i32,
+}
+
+impl Rating {
+    /// 获取玩家的分数，没有记录时返回初始分
+    pub async fn get(storage: &Storage, player_id: i32) -> Result<i32> {
+        let rating = storage.ratings.rating(player_id).await?;
+        Ok(rating.unwrap_or(DEFAULT_RATING))
+    }
+
+    /// 按完赛名次（第一名在前）结算一局，返回正式玩家的新分数；
+    /// 游客按初始分参与计算但不写入存储
+    pub async fn apply_finishing_order(
+        storage: &Storage,
+        finishing_order: &[i32],
+        guest_ids: &HashSet<i32>,
+    ) -> Result<HashMap<i32, i32>> {
+        storage
+            .ratings
+            .apply_finishing_order(finishing_order, guest_ids)
+            .await
+    }
+}
+
+/// 多人 Elo：按名次两两比较，名次靠前者记为胜。ratings 按完赛名次排列，返回每名玩家的分数变化
+pub fn elo_deltas(ratings: &[i32]) -> Vec<i32> {
+    let n = ratings.len();
+    if n < 2 {
+        return vec![0; n];
+    }
+    let k = K_FACTOR / (n - 1) as f64;
+    let mut deltas = vec![0.0; n];
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let expected = 1.0 / (1.0 + 10f64.powf((ratings[j] - ratings[i]) as f64 / 400.0));
+            deltas[i] += k * (1.0 - expected);
+            deltas[j] -= k * (1.0 - expected);
+        }
+    }
+    deltas.into_iter().map(|d| d.round() as i32).collect()
+}
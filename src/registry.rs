@@ -0,0 +1,150 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use dashmap::DashMap;
+use tokio::sync::{mpsc, watch};
+use tracing::debug;
+
+use crate::{QuitReason, ServerMessage};
+
+// 每个连接的待发送消息上限，超出时丢弃（客户端过慢）
+const CONNECTION_BUFFER: usize = 32;
+
+// 连接类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionKind {
+    // /ws/session：玩家级连接，不依赖房间
+    Session,
+    // /ws/lobby：大厅房间列表
+    Lobby,
+    // /ws：房间连接（玩家或观战者）
+    Room,
+    // /ws/match：快速匹配
+    Match,
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub connection_id: u64,
+    pub kind: ConnectionKind,
+    // 房间连接所在的房间
+    pub room_id: Option<String>,
+    sender: mpsc::Sender<ServerMessage>,
+    // 要求连接以指定原因关闭
+    closer: watch::Sender<Option<QuitReason>>,
+}
+
+// 全服连接表：玩家 id -> 该玩家所有在线连接的发送端，其他模块通过它向任意在线玩家推送消息
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    next_id: AtomicU64,
+    connections: DashMap<i32, Vec<Connection>>,
+}
+
+impl ConnectionRegistry {
+    /// 登记一个连接，返回的 guard 被 drop 时自动注销，receiver 接收推送给该连接的消息
+    pub fn register(
+        self: &Arc<Self>,
+        player_id: i32,
+        kind: ConnectionKind,
+        room_id: Option<String>,
+    ) -> (ConnectionGuard, mpsc::Receiver<ServerMessage>) {
+        let connection_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(CONNECTION_BUFFER);
+        let (closer, closed) = watch::channel(None);
+        self.connections.entry(player_id).or_default().push(Connection {
+            connection_id,
+            kind,
+            room_id,
+            sender,
+            closer,
+        });
+        debug!(
+            "🔗 [registry] 玩家 {} 登记连接 {} ({:?})",
+            player_id, connection_id, kind
+        );
+        let guard = ConnectionGuard {
+            registry: self.clone(),
+            player_id,
+            connection_id,
+            closed,
+        };
+        (guard, receiver)
+    }
+
+    fn unregister(&self, player_id: i32, connection_id: u64) {
+        self.connections
+            .remove_if_mut(&player_id, |_, connections| {
+                connections.retain(|c| c.connection_id != connection_id);
+                connections.is_empty()
+            });
+        debug!("🔗 [registry] 玩家 {} 注销连接 {}", player_id, connection_id);
+    }
+
+    /// 推送消息到玩家的所有在线连接，返回收到消息的连接数；缓冲区已满的连接丢弃该消息
+    pub fn send(&self, player_id: i32, message: &ServerMessage) -> usize {
+        self.connections.get(&player_id).map_or(0, |connections| {
+            connections
+                .iter()
+                .filter(|c| c.sender.try_send(message.clone()).is_ok())
+                .count()
+        })
+    }
+
+    /// 要求关闭玩家的某个连接，返回连接是否存在
+    pub fn close(&self, player_id: i32, connection_id: u64, reason: QuitReason) -> bool {
+        self.connections.get(&player_id).is_some_and(|connections| {
+            connections
+                .iter()
+                .find(|c| c.connection_id == connection_id)
+                .is_some_and(|c| c.closer.send(Some(reason)).is_ok())
+        })
+    }
+
+    pub fn is_online(&self, player_id: i32) -> bool {
+        self.connections.contains_key(&player_id)
+    }
+
+    /// 玩家当前的连接（不含发送端）
+    pub fn connections_of(&self, player_id: i32) -> Vec<(u64, ConnectionKind, Option<String>)> {
+        self.connections.get(&player_id).map_or_else(Vec::new, |connections| {
+            connections
+                .iter()
+                .map(|c| (c.connection_id, c.kind, c.room_id.clone()))
+                .collect()
+        })
+    }
+}
+
+// 连接登记凭证，连接结束时 drop 即注销
+pub struct ConnectionGuard {
+    registry: Arc<ConnectionRegistry>,
+    player_id: i32,
+    connection_id: u64,
+    closed: watch::Receiver<Option<QuitReason>>,
+}
+
+impl ConnectionGuard {
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+
+    /// 等待连接被要求关闭，返回关闭原因
+    pub async fn closed(&mut self) -> QuitReason {
+        if let Ok(reason) = self.closed.wait_for(Option::is_some).await
+            && let Some(reason) = *reason
+        {
+            return reason;
+        }
+        // guard 存活期间发送端不会被注销
+        std::future::pending().await
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.registry.unregister(self.player_id, self.connection_id);
+    }
+}
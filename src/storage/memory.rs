@@ -0,0 +1,196 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{
+    Account, DEFAULT_RATING, FriendRepository, PlayerRepository, Rating, RatingRepository,
+    SimplePlayer, elo_deltas,
+};
+
+// 只保存在内存中的存储，约束与 sql/init.sql 中的表保持一致
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: Mutex<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+    // player_id_seq
+    last_player_id: i32,
+    // player_info
+    accounts: HashMap<i32, Account>,
+    // friend_mapping，(master_id, friend_id)
+    friends: HashSet<(i32, i32)>,
+    // player_rating
+    ratings: HashMap<i32, Rating>,
+}
+
+impl MemoryStorage {
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl PlayerRepository for MemoryStorage {
+    async fn allocate_id(&self) -> Result<i32> {
+        let mut tables = self.tables();
+        tables.last_player_id += 1;
+        Ok(tables.last_player_id)
+    }
+
+    async fn create_account(
+        &self,
+        player_id: Option<i32>,
+        username: &str,
+        player_name: &str,
+        password_hash: &str,
+    ) -> Result<i32> {
+        let mut tables = self.tables();
+        if tables.accounts.values().any(|account| account.username == username) {
+            return Err(anyhow::anyhow!("用户名已存在"));
+        }
+        let player_id = match player_id {
+            Some(player_id) if tables.accounts.contains_key(&player_id) => {
+                return Err(anyhow::anyhow!("玩家已存在"));
+            }
+            Some(player_id) => player_id,
+            None => {
+                tables.last_player_id += 1;
+                tables.last_player_id
+            }
+        };
+        tables.last_player_id = tables.last_player_id.max(player_id);
+        tables.accounts.insert(
+            player_id,
+            Account {
+                player_id,
+                username: username.to_string(),
+                player_name: player_name.to_string(),
+                password_hash: password_hash.to_string(),
+                is_admin: false,
+                token_version: 0,
+            },
+        );
+        Ok(player_id)
+    }
+
+    async fn account_by_username(&self, username: &str) -> Result<Option<Account>> {
+        Ok(self
+            .tables()
+            .accounts
+            .values()
+            .find(|account| account.username == username)
+            .cloned())
+    }
+
+    async fn account(&self, player_id: i32) -> Result<Option<Account>> {
+        Ok(self.tables().accounts.get(&player_id).cloned())
+    }
+
+    async fn set_password_hash(&self, player_id: i32, password_hash: &str) -> Result<bool> {
+        match self.tables().accounts.get_mut(&player_id) {
+            Some(account) => {
+                account.password_hash = password_hash.to_string();
+                account.token_version += 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_admin(&self, player_id: i32, is_admin: bool) -> Result<bool> {
+        match self.tables().accounts.get_mut(&player_id) {
+            Some(account) => {
+                account.is_admin = is_admin;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn player(&self, player_id: i32) -> Result<Option<SimplePlayer>> {
+        Ok(self
+            .tables()
+            .accounts
+            .get(&player_id)
+            .map(|account| SimplePlayer::new(account.player_id, account.player_name.clone())))
+    }
+}
+
+#[async_trait]
+impl FriendRepository for MemoryStorage {
+    async fn add_friend(&self, master_id: i32, friend_id: i32) -> Result<()> {
+        let mut tables = self.tables();
+        // 外键和主键约束
+        if !tables.accounts.contains_key(&master_id) || !tables.accounts.contains_key(&friend_id) {
+            return Err(anyhow::anyhow!("玩家不存在"));
+        }
+        if tables.friends.contains(&(master_id, friend_id))
+            || tables.friends.contains(&(friend_id, master_id))
+        {
+            return Err(anyhow::anyhow!("已经是好友"));
+        }
+        tables.friends.insert((master_id, friend_id));
+        tables.friends.insert((friend_id, master_id));
+        Ok(())
+    }
+
+    async fn remove_friend(&self, master_id: i32, friend_id: i32) -> Result<bool> {
+        Ok(self.tables().friends.remove(&(master_id, friend_id)))
+    }
+
+    async fn friends_of(&self, master_id: i32) -> Result<Vec<SimplePlayer>> {
+        let tables = self.tables();
+        Ok(tables
+            .friends
+            .iter()
+            .filter(|(master, _)| *master == master_id)
+            .filter_map(|(_, friend_id)| tables.accounts.get(friend_id))
+            .map(|account| SimplePlayer::new(account.player_id, account.player_name.clone()))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl RatingRepository for MemoryStorage {
+    async fn rating(&self, player_id: i32) -> Result<Option<i32>> {
+        Ok(self.tables().ratings.get(&player_id).map(|r| r.rating))
+    }
+
+    async fn apply_finishing_order(
+        &self,
+        finishing_order: &[i32],
+        guest_ids: &HashSet<i32>,
+    ) -> Result<HashMap<i32, i32>> {
+        // 整局在同一把锁内结算，并发结算依次进行
+        let mut tables = self.tables();
+        let ratings: Vec<i32> = finishing_order
+            .iter()
+            .map(|id| match guest_ids.contains(id) {
+                true => DEFAULT_RATING,
+                false => tables.ratings.get(id).map_or(DEFAULT_RATING, |r| r.rating),
+            })
+            .collect();
+        let deltas = elo_deltas(&ratings);
+        let mut updated = HashMap::new();
+        for ((id, rating), delta) in finishing_order.iter().zip(ratings).zip(deltas) {
+            if guest_ids.contains(id) {
+                continue;
+            }
+            let entry = tables.ratings.entry(*id).or_insert(Rating {
+                player_id: *id,
+                rating: DEFAULT_RATING,
+                games_played: 0,
+            });
+            entry.rating = rating + delta;
+            entry.games_played += 1;
+            updated.insert(*id, rating + delta);
+        }
+        Ok(updated)
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{Account, SimplePlayer, StorageBackend};
+
+pub mod memory;
+pub use memory::*;
+pub mod postgres;
+pub use postgres::*;
+
+/// 玩家账号（player_info 表）
+#[async_trait]
+pub trait PlayerRepository: Send + Sync {
+    /// 从玩家 id 序列中分配 id，游客和正式账号共用同一个序列
+    async fn allocate_id(&self) -> Result<i32>;
+
+    /// 创建账号并返回 player_id；指定 player_id 时沿用该 id（游客升级）
+    async fn create_account(
+        &self,
+        player_id: Option<i32>,
+        username: &str,
+        player_name: &str,
+        password_hash: &str,
+    ) -> Result<i32>;
+
+    async fn account_by_username(&self, username: &str) -> Result<Option<Account>>;
+
+    /// 只返回设置了登录凭证的账号
+    async fn account(&self, player_id: i32) -> Result<Option<Account>>;
+
+    /// 更新密码哈希并递增 token_version，返回账号是否存在
+    async fn set_password_hash(&self, player_id: i32, password_hash: &str) -> Result<bool>;
+
+    /// 设置或取消管理员，返回账号是否存在
+    async fn set_admin(&self, player_id: i32, is_admin: bool) -> Result<bool>;
+
+    async fn player(&self, player_id: i32) -> Result<Option<SimplePlayer>>;
+}
+
+/// 正式玩家之间的好友关系（friend_mapping 表），每个方向单独保存一条
+#[async_trait]
+pub trait FriendRepository: Send + Sync {
+    /// 双向添加好友，双方都必须是已存在的玩家
+    async fn add_friend(&self, master_id: i32, friend_id: i32) -> Result<()>;
+
+    /// 删除 master_id 一方的关系，返回是否存在该关系
+    async fn remove_friend(&self, master_id: i32, friend_id: i32) -> Result<bool>;
+
+    async fn friends_of(&self, master_id: i32) -> Result<Vec<SimplePlayer>>;
+}
+
+/// 段位分（player_rating 表）
+#[async_trait]
+pub trait RatingRepository: Send + Sync {
+    /// 玩家的分数，没有记录时返回 None
+    async fn rating(&self, player_id: i32) -> Result<Option<i32>>;
+
+    /// 按完赛名次（第一名在前）结算一局，返回正式玩家的新分数；
+    /// 游客按初始分参与计算但不写入。同一批玩家的并发结算不能互相覆盖
+    async fn apply_finishing_order(
+        &self,
+        finishing_order: &[i32],
+        guest_ids: &HashSet<i32>,
+    ) -> Result<HashMap<i32, i32>>;
+}
+
+/// 服务器使用的全部存储，后端由配置中的 storage 选择
+#[derive(Clone)]
+pub struct Storage {
+    pub players: Arc<dyn PlayerRepository>,
+    pub friends: Arc<dyn FriendRepository>,
+    pub ratings: Arc<dyn RatingRepository>,
+}
+
+impl Storage {
+    /// 按配置创建存储；Postgres 连接池延迟建立，连接串无效时返回错误
+    pub fn try_new(backend: StorageBackend, database: &str) -> Result<Self> {
+        match backend {
+            StorageBackend::Postgres => Ok(Self::from_backend(Arc::new(PgStorage::connect_lazy(
+                database,
+            )?))),
+            StorageBackend::Memory => Ok(Self::memory()),
+        }
+    }
+
+    /// 只保存在内存中的存储，进程退出后数据丢失；用于测试和本地演示
+    pub fn memory() -> Self {
+        Self::from_backend(Arc::new(MemoryStorage::default()))
+    }
+
+    fn from_backend<B>(backend: Arc<B>) -> Self
+    where
+        B: PlayerRepository + FriendRepository + RatingRepository + 'static,
+    {
+        Self {
+            players: backend.clone(),
+            friends: backend.clone(),
+            ratings: backend,
+        }
+    }
+}
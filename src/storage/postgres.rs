@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{
+    Account, DEFAULT_RATING, FriendRepository, PlayerRepository, Rating, RatingRepository,
+    SimplePlayer, elo_deltas,
+};
+
+// 表结构见 sql/init.sql
+pub struct PgStorage {
+    pool: PgPool,
+}
+
+impl PgStorage {
+    /// 创建连接池，第一次查询时才真正连接数据库
+    pub fn connect_lazy(url: &str) -> Result<Self> {
+        let pool = PgPool::connect_lazy(url)?;
+        info!("Postgres connection pool created successfully");
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl PlayerRepository for PgStorage {
+    async fn allocate_id(&self) -> Result<i32> {
+        let id: i64 = sqlx::query_scalar("SELECT nextval('player_id_seq')")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(i32::try_from(id)?)
+    }
+
+    async fn create_account(
+        &self,
+        player_id: Option<i32>,
+        username: &str,
+        player_name: &str,
+        password_hash: &str,
+    ) -> Result<i32> {
+        // 未指定 player_id 时使用列默认值 nextval('player_id_seq')
+        let query = match player_id {
+            Some(player_id) => sqlx::query_scalar(
+                "INSERT INTO player_info (player_id, player_name, username, password_hash) VALUES ($1, $2, $3, $4) RETURNING player_id",
+            )
+            .bind(player_id),
+            None => sqlx::query_scalar(
+                "INSERT INTO player_info (player_name, username, password_hash) VALUES ($1, $2, $3) RETURNING player_id",
+            ),
+        };
+        let player_id: i32 = query
+            .bind(player_name)
+            .bind(username)
+            .bind(password_hash)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(player_id)
+    }
+
+    async fn account_by_username(&self, username: &str) -> Result<Option<Account>> {
+        let account = sqlx::query_as(
+            "SELECT player_id, username, player_name, password_hash, is_admin, token_version FROM player_info WHERE username = $1",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(account)
+    }
+
+    async fn account(&self, player_id: i32) -> Result<Option<Account>> {
+        let account = sqlx::query_as(
+            "SELECT player_id, username, player_name, password_hash, is_admin, token_version FROM player_info WHERE player_id = $1 AND username IS NOT NULL",
+        )
+        .bind(player_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(account)
+    }
+
+    async fn set_password_hash(&self, player_id: i32, password_hash: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE player_info SET password_hash = $1, token_version = token_version + 1 WHERE player_id = $2",
+        )
+        .bind(password_hash)
+        .bind(player_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_admin(&self, player_id: i32, is_admin: bool) -> Result<bool> {
+        let result = sqlx::query("UPDATE player_info SET is_admin = $1 WHERE player_id = $2")
+            .bind(is_admin)
+            .bind(player_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn player(&self, player_id: i32) -> Result<Option<SimplePlayer>> {
+        let player =
+            sqlx::query_as("SELECT player_id, player_name FROM player_info WHERE player_id = $1")
+                .bind(player_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(player)
+    }
+}
+
+#[async_trait]
+impl FriendRepository for PgStorage {
+    async fn add_friend(&self, master_id: i32, friend_id: i32) -> Result<()> {
+        // 双向添加好友
+        let result1 =
+            sqlx::query("INSERT INTO friend_mapping (master_id, friend_id) VALUES ($1, $2)")
+                .bind(master_id)
+                .bind(friend_id)
+                .execute(&self.pool)
+                .await?;
+
+        let result2 =
+            sqlx::query("INSERT INTO friend_mapping (master_id,friend_id) VALUES ($1, $2)")
+                .bind(friend_id)
+                .bind(master_id)
+                .execute(&self.pool)
+                .await?;
+        if result1.rows_affected() == 0 && result2.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("添加好友失败"));
+        }
+        Ok(())
+    }
+
+    async fn remove_friend(&self, master_id: i32, friend_id: i32) -> Result<bool> {
+        let result =
+            sqlx::query(r#"DELETE FROM friend_mapping WHERE master_id = $1 AND friend_id = $2"#)
+                .bind(master_id)
+                .bind(friend_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn friends_of(&self, master_id: i32) -> Result<Vec<SimplePlayer>> {
+        let friends = sqlx::query_as("SELECT player_id, player_name FROM player_info WHERE player_id IN (SELECT friend_id FROM friend_mapping WHERE master_id = $1)")
+            .bind(master_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(friends)
+    }
+}
+
+#[async_trait]
+impl RatingRepository for PgStorage {
+    async fn rating(&self, player_id: i32) -> Result<Option<i32>> {
+        let rating = sqlx::query_scalar("SELECT rating FROM player_rating WHERE player_id = $1")
+            .bind(player_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(rating)
+    }
+
+    async fn apply_finishing_order(
+        &self,
+        finishing_order: &[i32],
+        guest_ids: &HashSet<i32>,
+    ) -> Result<HashMap<i32, i32>> {
+        let rated_ids: Vec<i32> = finishing_order
+            .iter()
+            .copied()
+            .filter(|id| !guest_ids.contains(id))
+            .collect();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO player_rating (player_id) SELECT * FROM UNNEST($1::INT[]) ON CONFLICT DO NOTHING",
+        )
+        .bind(&rated_ids)
+        .execute(&mut *tx)
+        .await?;
+        // 锁定参与结算的行，避免并发结算互相覆盖
+        let current: Vec<Rating> = sqlx::query_as(
+            "SELECT player_id, rating, games_played FROM player_rating WHERE player_id = ANY($1) FOR UPDATE",
+        )
+        .bind(&rated_ids)
+        .fetch_all(&mut *tx)
+        .await?;
+        let current: HashMap<i32, i32> = current.into_iter().map(|r| (r.player_id, r.rating)).collect();
+
+        let ratings: Vec<i32> = finishing_order
+            .iter()
+            .map(|id| current.get(id).copied().unwrap_or(DEFAULT_RATING))
+            .collect();
+        let deltas = elo_deltas(&ratings);
+        let mut updated = HashMap::new();
+        for ((id, rating), delta) in finishing_order.iter().zip(ratings).zip(deltas) {
+            if guest_ids.contains(id) {
+                continue;
+            }
+            sqlx::query(
+                "UPDATE player_rating SET rating = $2, games_played = games_played + 1 WHERE player_id = $1",
+            )
+            .bind(id)
+            .bind(rating + delta)
+            .execute(&mut *tx)
+            .await?;
+            updated.insert(*id, rating + delta);
+        }
+        tx.commit().await?;
+        Ok(updated)
+    }
+}
@@ -1,34 +1,547 @@
-use crate::{dto::MessageResponse, MessageType};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::MessageType;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Room {
-    pub room_id: i32,
+    // 服务器生成的短房间码，可分享给好友
+    pub room_id: String,
+    // 房主
+    pub owner_id: i32,
     pub players: Vec<Player>,
     pub cars: Vec<Car>,
     pub weather_id: i32,
     pub background_id: i32,
+    // 房间最大人数
+    pub max_players: usize,
+    // 每辆车的座位数
+    pub car_seats: usize,
+    pub visibility: RoomVisibility,
+    // 房间密码的 argon2 哈希，不下发给客户端
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    // 房主生成的一次性邀请码，不下发给客户端
+    #[serde(skip)]
+    pub invite_codes: HashSet<String>,
+    // 观战者：订阅房间广播，不占用座位
+    pub spectators: Vec<Spectator>,
+    // 是否允许观战者发送聊天消息
+    pub spectator_chat: bool,
+    // 被房主封禁的玩家，房间存在期间不能再加入
+    #[serde(skip)]
+    pub banned_ids: HashSet<i32>,
+    pub phase: RoomPhase,
+    // 每次切换阶段递增，定时推进阶段时用于判断是否已过期
+    #[serde(skip)]
+    pub phase_epoch: u64,
+    // 已提交成绩的比赛（提交时的 phase_epoch），同一局只结算一次
+    #[serde(skip)]
+    pub settled_epoch: Option<u64>,
+    // 每个成员当前的连接及重连凭证，不下发给客户端
+    #[serde(skip)]
+    pub sessions: HashMap<i32, SeatSession>,
+    // 最后一名玩家离开后房间关闭，房间任务随之结束
+    #[serde(skip)]
+    pub closed: bool,
+}
+
+// 成员离开房间的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Departure {
+    pub was_player: bool,
+    // 房主离开后接任的新房主
+    pub new_owner: Option<i32>,
+    // 离开的是最后一名已准备的玩家，房间回到 Lobby
+    pub phase_changed: bool,
+    // 最后一名玩家离开，房间关闭
+    pub closed: bool,
+}
+
+// 成员在房间内的会话：断线重连时凭 resume_token 接回原座位
+#[derive(Clone)]
+pub struct SeatSession {
+    pub resume_token: String,
+    // 当前持有座位的连接，旧连接断开时据此判断是否已被新连接取代
+    pub connection_id: u64,
+}
+
+// 房间快照会写入调试日志，不输出重连凭证
+impl std::fmt::Debug for SeatSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SeatSession")
+            .field("resume_token", &"<redacted>")
+            .field("connection_id", &self.connection_id)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Spectator {
+    pub player_id: i32,
+    pub player_name: String,
+}
+
+// 服务器主动断开玩家房间连接的原因，决定关闭帧的 code 和 reason
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuitReason {
+    // 玩家调用 /quitroom
+    UserQuit,
+    // 被房主踢出
+    Kicked,
+    // 被房主封禁
+    Banned,
+    // 最后一名玩家离开，房间销毁（断开剩余的观战者）
+    RoomClosed,
+    // 同一玩家在别处建立了新的房间连接
+    Replaced,
+}
+
+impl QuitReason {
+    pub fn close_code(self) -> u16 {
+        match self {
+            QuitReason::UserQuit => 1000,
+            QuitReason::Kicked => 4001,
+            QuitReason::Banned => 4003,
+            QuitReason::RoomClosed => 1001,
+            QuitReason::Replaced => 4002,
+        }
+    }
+
+    pub fn close_reason(self) -> &'static str {
+        match self {
+            QuitReason::UserQuit => "User quit",
+            QuitReason::Kicked => "Kicked by host",
+            QuitReason::Banned => "Banned by host",
+            QuitReason::RoomClosed => "Room closed",
+            QuitReason::Replaced => "Connected elsewhere",
+        }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+// 房间阶段：Lobby -> ReadyCheck -> Countdown -> Racing -> Results -> Lobby
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomPhase {
+    // 等待玩家，可以换车、修改房间设置
+    #[default]
+    Lobby,
+    // 至少一名玩家已准备，全员准备后房主可以开始
+    ReadyCheck,
+    // 倒计时中，由服务器推进到 Racing
+    Countdown,
+    // 比赛中，房主提交成绩后进入 Results
+    Racing,
+    // 展示结果，由服务器推进回 Lobby
+    Results,
+}
+
+impl RoomPhase {
+    /// 是否允许从当前阶段切换到目标阶段
+    pub fn can_transition_to(self, next: RoomPhase) -> bool {
+        use RoomPhase::*;
+        matches!(
+            (self, next),
+            (Lobby, ReadyCheck)
+                | (ReadyCheck, Lobby)
+                | (ReadyCheck, Countdown)
+                | (Countdown, Racing)
+                | (Racing, Results)
+                | (Results, Lobby)
+        )
+    }
+
+    /// 是否处于比赛之外，可以加入房间、换车、准备和修改设置
+    pub fn is_idle(self) -> bool {
+        matches!(self, RoomPhase::Lobby | RoomPhase::ReadyCheck)
+    }
+}
+
+// 房间可见性
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomVisibility {
+    // 任何人都可以加入
+    #[default]
+    Public,
+    // 仅房主的好友可以加入（或持有邀请码）
+    Friends,
+    // 需要房间密码或邀请码
+    Private,
+}
+
+impl Room {
+    /// 切换阶段；回到 Lobby 时清除所有玩家的准备状态
+    pub fn transition(&mut self, next: RoomPhase) -> Result<(), &'static str> {
+        if !self.phase.can_transition_to(next) {
+            return Err("当前阶段不允许该操作");
+        }
+        self.enter_phase(next);
+        Ok(())
+    }
+
+    /// 登记本局成绩：比赛进行中且本局尚未提交过成绩时返回本局的 epoch
+    pub fn claim_result(&mut self) -> Result<u64, &'static str> {
+        if self.phase != RoomPhase::Racing {
+            return Err("比赛未在进行中");
+        }
+        if self.settled_epoch == Some(self.phase_epoch) {
+            return Err("本局成绩已提交");
+        }
+        self.settled_epoch = Some(self.phase_epoch);
+        Ok(self.phase_epoch)
+    }
+
+    /// 结算失败时撤销登记，允许重新提交本局成绩
+    pub fn release_result(&mut self, epoch: u64) {
+        if self.settled_epoch == Some(epoch) {
+            self.settled_epoch = None;
+        }
+    }
+
+    /// 中止比赛：超时仍没有提交成绩时直接回到 Lobby，不产生结果
+    pub fn abort_race(&mut self) -> Result<(), &'static str> {
+        if self.phase != RoomPhase::Racing {
+            return Err("当前不在比赛中");
+        }
+        self.enter_phase(RoomPhase::Lobby);
+        Ok(())
+    }
+
+    fn enter_phase(&mut self, next: RoomPhase) {
+        self.phase = next;
+        self.phase_epoch += 1;
+        if next == RoomPhase::Lobby {
+            self.players.iter_mut().for_each(|p| p.ready = false);
+        }
+    }
+
+    /// 设置玩家准备状态，并在 Lobby 与 ReadyCheck 之间切换；返回切换后的阶段（未切换时为 None）
+    pub fn set_ready(&mut self, player_id: i32, ready: bool) -> Result<Option<RoomPhase>, &'static str> {
+        if !self.phase.is_idle() {
+            return Err("比赛进行中");
+        }
+        let player = self
+            .players
+            .iter_mut()
+            .find(|p| p.player_id == player_id)
+            .ok_or("玩家不在房间中")?;
+        player.ready = ready;
+        let any_ready = self.players.iter().any(|p| p.ready);
+        let next = match (self.phase, any_ready) {
+            (RoomPhase::Lobby, true) => RoomPhase::ReadyCheck,
+            (RoomPhase::ReadyCheck, false) => RoomPhase::Lobby,
+            _ => return Ok(None),
+        };
+        self.transition(next)?;
+        Ok(Some(next))
+    }
+
+    /// 房主开始比赛：所有玩家都已准备时进入倒计时
+    pub fn start_countdown(&mut self) -> Result<(), &'static str> {
+        if self.phase != RoomPhase::ReadyCheck || !self.players.iter().all(|p| p.ready) {
+            return Err("还有玩家未准备");
+        }
+        self.transition(RoomPhase::Countdown)
+    }
+
+    pub fn is_owner(&self, player_id: i32) -> bool {
+        self.owner_id == player_id
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.players.len() >= self.max_players
+    }
+
+    /// 移除玩家及其车辆，返回被移除的玩家
+    pub fn remove_player(&mut self, player_id: i32) -> Option<Player> {
+        let pos = self.players.iter().position(|p| p.player_id == player_id)?;
+        let player = self.players.remove(pos);
+        self.sessions.remove(&player_id);
+        self.leave_car(player_id);
+        Some(player)
+    }
 
+    /// car_id 对应的车辆是否还能坐下该玩家（车辆不存在时会新建）
+    pub fn has_car_seat(&self, car_id: i32, player_id: i32) -> bool {
+        self.cars
+            .iter()
+            .find(|car| car.car_id == car_id)
+            .is_none_or(|car| car.player_ids.contains(&player_id) || !car.is_full(self.car_seats))
+    }
+
+    /// 玩家坐进 car_id 对应的车辆：车辆已存在时占用空座，不存在时以 skin_id 新建；
+    /// 加入房间、观战者占座和换车都经过这里，保证车辆不超座且 car_id 不重复
+    pub fn board_car(&mut self, player_id: i32, car_id: i32, skin_id: i32) -> Result<(), &'static str> {
+        if !self.has_car_seat(car_id, player_id) {
+            return Err("车辆已满");
+        }
+        self.leave_car(player_id);
+        match self.cars.iter_mut().find(|car| car.car_id == car_id) {
+            Some(car) => car.player_ids.push(player_id),
+            None => self.cars.push(Car {
+                car_id,
+                skin_id,
+                player_ids: vec![player_id],
+            }),
+        }
+        if let Some(player) = self.players.iter_mut().find(|p| p.player_id == player_id) {
+            player.car_id = car_id;
+        }
+        Ok(())
+    }
+
+    // 离开所乘坐的车辆，没有乘客的车辆随之移除
+    fn leave_car(&mut self, player_id: i32) {
+        for car in &mut self.cars {
+            car.player_ids.retain(|id| *id != player_id);
+        }
+        self.cars.retain(|car| !car.player_ids.is_empty());
+    }
+
+    /// 玩家是否在房间中（占座或观战）
+    pub fn has_member(&self, player_id: i32) -> bool {
+        self.players.iter().any(|p| p.player_id == player_id) || self.is_spectator(player_id)
+    }
+
+    pub fn is_spectator(&self, player_id: i32) -> bool {
+        self.spectators.iter().any(|s| s.player_id == player_id)
+    }
+
+    /// 移除观战者，返回是否存在
+    pub fn remove_spectator(&mut self, player_id: i32) -> bool {
+        let before = self.spectators.len();
+        self.spectators.retain(|s| s.player_id != player_id);
+        self.spectators.len() != before
+    }
+
+    /// 观战者在空闲阶段且有空位时成为玩家
+    pub fn promote_spectator(&mut self, player: Player, skin_id: i32) -> Result<(), &'static str> {
+        if !self.is_spectator(player.player_id) {
+            return Err("玩家不在观战");
+        }
+        if !self.phase.is_idle() {
+            return Err("比赛进行中");
+        }
+        if self.is_full() {
+            return Err("房间已满");
+        }
+        self.board_car(player.player_id, player.car_id, skin_id)?;
+        self.remove_spectator(player.player_id);
+        self.players.push(player);
+        Ok(())
+    }
+
+    /// 房主不在座位上时（创建后没有连接 /ws、匹配后没有加入），刚坐下的玩家接任房主；返回是否接任
+    pub fn claim_vacant_host(&mut self, player_id: i32) -> bool {
+        let owner_seated = self.players.iter().any(|p| p.player_id == self.owner_id);
+        if owner_seated || !self.players.iter().any(|p| p.player_id == player_id) {
+            return false;
+        }
+        self.owner_id = player_id;
+        true
+    }
+
+    /// 房间内玩家的平均分，房间为空时返回 None
+    pub fn average_rating(&self) -> Option<f64> {
+        if self.players.is_empty() {
+            return None;
+        }
+        let total: i64 = self.players.iter().map(|p| p.rating as i64).sum();
+        Some(total as f64 / self.players.len() as f64)
+    }
+
+    /// 为成员的新连接生成重连凭证，取代之前的连接，返回新凭证
+    pub fn attach_session(&mut self, player_id: i32, connection_id: u64) -> String {
+        let resume_token = uuid::Uuid::new_v4().simple().to_string();
+        self.sessions.insert(
+            player_id,
+            SeatSession {
+                resume_token: resume_token.clone(),
+                connection_id,
+            },
+        );
+        resume_token
+    }
+
+    /// 连接是否仍持有该成员的座位（没有登记会话时视为持有）
+    pub fn is_current_connection(&self, player_id: i32, connection_id: u64) -> bool {
+        self.sessions
+            .get(&player_id)
+            .is_none_or(|session| session.connection_id == connection_id)
+    }
+
+    /// 重连凭证是否属于仍在座位上的玩家
+    pub fn can_resume(&self, player_id: i32, resume_token: &str) -> bool {
+        self.players.iter().any(|p| p.player_id == player_id)
+            && self
+                .sessions
+                .get(&player_id)
+                .is_some_and(|session| session.resume_token == resume_token)
+    }
+
+    /// 设置玩家的重连状态，返回玩家是否在座位上
+    pub fn set_reconnecting(&mut self, player_id: i32, reconnecting: bool) -> bool {
+        match self.players.iter_mut().find(|p| p.player_id == player_id) {
+            Some(player) => {
+                player.reconnecting = reconnecting;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 成员（玩家或观战者）离开：移除玩家及其车辆；最后一名已准备的玩家离开时回到 Lobby，
+    /// 房主离开时移交给在房间时间最长的玩家，最后一名玩家离开时房间关闭（观战者离开不会关闭房间）
+    pub fn leave(&mut self, player_id: i32) -> Result<Departure, &'static str> {
+        let was_player = self.remove_player(player_id).is_some();
+        if !was_player && !self.remove_spectator(player_id) {
+            return Err("玩家不存在");
+        }
+        self.sessions.remove(&player_id);
+        let phase_changed = self.phase == RoomPhase::ReadyCheck
+            && !self.players.iter().any(|p| p.ready)
+            && self.transition(RoomPhase::Lobby).is_ok();
+        let (new_owner, closed) = match self.next_owner() {
+            Some(next_owner) if self.is_owner(player_id) => {
+                self.owner_id = next_owner;
+                (Some(next_owner), false)
+            }
+            Some(_) => (None, false),
+            None => (None, was_player),
+        };
+        self.closed = closed;
+        Ok(Departure {
+            was_player,
+            new_owner,
+            phase_changed,
+            closed,
+        })
+    }
+
+    /// 房主离开后的继任者：players 按加入顺序排列，取在房间时间最长的玩家
+    pub fn next_owner(&self) -> Option<i32> {
+        self.players.first().map(|p| p.player_id)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Car {
     pub car_id: i32,
     pub skin_id: i32,
     pub player_ids:Vec<i32>
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+impl Car {
+    pub fn is_full(&self, car_seats: usize) -> bool {
+        self.player_ids.len() >= car_seats
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Player {
     pub player_id: i32,
     pub player_name: String,
     pub car_id: i32,
-    pub weather_id: i32,
-    pub background_id: i32,
+    // 加入房间时的段位分，游客为初始分
+    pub rating: i32,
+    // 以游客 token 加入，不参与分数结算
+    #[serde(default)]
+    pub guest: bool,
+    pub ready: bool,
+    // 掉线后等待重连，座位和车辆保留到宽限期结束
+    pub reconnecting: bool,
+}
+
+// 房间广播中的一条消息，seq 在房间内单调递增；Quit 只在服务器内部使用，不占用序号
+#[derive(Debug, Clone)]
+pub struct RoomEvent {
+    pub seq: u64,
+    pub message: MessageType,
+}
+
+// 房间广播通道：为每条消息编号并保留最近的历史，客户端落后或重连时据此补发
+#[derive(Clone)]
+pub struct RoomChannel {
+    sender: broadcast::Sender<RoomEvent>,
+    // 保持通道打开，房间没有连接时发送也不会失败
+    _receiver: Arc<broadcast::Receiver<RoomEvent>>,
+    history: Arc<Mutex<RoomHistory>>,
 }
 
-pub type room_broadcast_couple = (
-    broadcast::Sender<MessageType>,
-    broadcast::Receiver<MessageType>,
-);
+struct RoomHistory {
+    last_seq: u64,
+    events: VecDeque<RoomEvent>,
+    capacity: usize,
+}
+
+impl RoomChannel {
+    /// capacity 同时是广播缓冲区和历史记录的长度
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, receiver) = broadcast::channel(capacity);
+        Self {
+            sender,
+            _receiver: Arc::new(receiver),
+            history: Arc::new(Mutex::new(RoomHistory {
+                last_seq: 0,
+                events: VecDeque::with_capacity(capacity),
+                capacity,
+            })),
+        }
+    }
+
+    /// 编号并广播消息，编号和发送在同一把锁内完成，保证接收顺序与序号一致
+    pub fn send(&self, message: MessageType) -> Result<u64, broadcast::error::SendError<RoomEvent>> {
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(message, MessageType::Quit(..)) {
+            let seq = history.last_seq;
+            return self.sender.send(RoomEvent { seq, message }).map(|_| seq);
+        }
+        history.last_seq += 1;
+        let event = RoomEvent {
+            seq: history.last_seq,
+            message,
+        };
+        if history.events.len() >= history.capacity {
+            history.events.pop_front();
+        }
+        history.events.push_back(event.clone());
+        self.sender.send(event).map(|_| history.last_seq)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RoomEvent> {
+        self.sender.subscribe()
+    }
+
+    /// 最近一条消息的序号，房间还没有消息时为 0
+    pub fn last_seq(&self) -> u64 {
+        self.history.lock().unwrap_or_else(|e| e.into_inner()).last_seq
+    }
+
+    /// 序号大于 after_seq 的历史消息；需要的消息已被淘汰或序号无效时返回 None
+    pub fn replay_after(&self, after_seq: u64) -> Option<Vec<RoomEvent>> {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        if after_seq > history.last_seq {
+            return None;
+        }
+        let oldest = history.events.front().map_or(history.last_seq + 1, |e| e.seq);
+        if after_seq + 1 < oldest {
+            return None;
+        }
+        Some(
+            history
+                .events
+                .iter()
+                .filter(|e| e.seq > after_seq)
+                .cloned()
+                .collect(),
+        )
+    }
+}
@@ -4,17 +4,19 @@ WebSocket 测试脚本
 用于测试 minigame 的 WebSocket 接口
 
 使用方法:
-    python3 test_websocket.py --player-id 1 --room-id 1
+    python3 test_websocket.py --token <token> --room-id ABC234 --car-id 101 --skin-id 1
+
+    token 由 /login 或 /guest 返回；私密房间再加 --password
 
 依赖安装:
     pip3 install websocket-client
 """
 
-import asyncio
 import json
 import sys
 import argparse
 from datetime import datetime
+from urllib.parse import urlencode
 
 try:
     import websocket
@@ -25,9 +27,11 @@ except ImportError:
 
 
 class WebSocketClient:
-    def __init__(self, url, player_id, room_id):
-        self.url = f"{url}?player_id={player_id}&room_id={room_id}"
-        self.player_id = player_id
+    def __init__(self, url, token, room_id, car_id, skin_id, password=None):
+        params = {"token": token, "room_id": room_id, "car_id": car_id, "skin_id": skin_id}
+        if password:
+            params["password"] = password
+        self.url = f"{url}?{urlencode(params)}"
         self.room_id = room_id
         self.ws = None
         
@@ -52,12 +56,11 @@ class WebSocketClient:
     
     def on_open(self, ws):
         print(f"[{self.timestamp()}] ✅ 连接已建立")
-        print(f"    URL: {self.url}")
-        print(f"    玩家 ID: {self.player_id}")
-        print(f"    房间 ID: {self.room_id}")
+        print(f"    房间号: {self.room_id}")
         print()
         print("💡 提示:")
         print("  - 输入消息内容并按回车发送")
+        print("  - 以 '/emoji ' 开头发送表情")
         print("  - 输入 'quit' 或 'exit' 退出")
         print("  - 输入 'help' 查看帮助")
         print("-" * 50)
@@ -83,16 +86,18 @@ class WebSocketClient:
                     print("📖 帮助信息:")
                     print("  quit/exit - 退出程序")
                     print("  help      - 显示此帮助")
-                    print("  其他内容  - 发送消息")
+                    print("  /emoji xx - 发送表情")
+                    print("  其他内容  - 发送聊天消息")
                     print()
                     continue
                 elif not content.strip():
                     continue
                 
-                message = {
-                    "player_id": self.player_id,
-                    "content": content
-                }
+                # 发送者身份由连接决定，不需要携带 player_id
+                if content.startswith("/emoji "):
+                    message = {"type": "emoji", "content": content[len("/emoji "):]}
+                else:
+                    message = {"type": "text", "content": content}
                 
                 self.ws.send(json.dumps(message))
                 print(f"[{self.timestamp()}] 📤 已发送: {content}")
@@ -106,7 +111,7 @@ class WebSocketClient:
     
     def connect(self):
         """连接到 WebSocket 服务器"""
-        print(f"[{self.timestamp()}] 🔌 正在连接到: {self.url}")
+        print(f"[{self.timestamp()}] 🔌 正在连接到房间: {self.room_id}")
         
         websocket.enableTrace(False)
         self.ws = websocket.WebSocketApp(
@@ -120,7 +125,7 @@ class WebSocketClient:
         self.ws.run_forever()
 
 
-def test_connection_errors():
+def test_connection_errors(url, token):
     """测试各种错误情况"""
     print("=" * 50)
     print("测试连接错误情况")
@@ -128,28 +133,31 @@ def test_connection_errors():
     
     test_cases = [
         {
-            "name": "缺少 player_id 参数",
-            "url": "ws://localhost:7777/ws?room_id=1",
+            "name": "缺少 token 参数",
+            "params": {"room_id": "ABC234", "car_id": 101, "skin_id": 1},
+        },
+        {
+            "name": "token 无效",
+            "params": {"token": "invalid", "room_id": "ABC234", "car_id": 101, "skin_id": 1},
         },
         {
             "name": "缺少 room_id 参数",
-            "url": "ws://localhost:7777/ws?player_id=1",
+            "params": {"token": token, "car_id": 101, "skin_id": 1},
         },
         {
-            "name": "player_id 格式错误",
-            "url": "ws://localhost:7777/ws?player_id=abc&room_id=1",
+            "name": "房间不存在",
+            "params": {"token": token, "room_id": "000000", "car_id": 101, "skin_id": 1},
         },
         {
-            "name": "room_id 格式错误",
-            "url": "ws://localhost:7777/ws?player_id=1&room_id=xyz",
+            "name": "car_id 格式错误",
+            "params": {"token": token, "room_id": "ABC234", "car_id": "abc", "skin_id": 1},
         },
     ]
     
     for test in test_cases:
         print(f"\n测试: {test['name']}")
-        print(f"URL: {test['url']}")
         try:
-            ws = websocket.create_connection(test['url'], timeout=2)
+            ws = websocket.create_connection(f"{url}?{urlencode(test['params'])}", timeout=2)
             print("❌ 应该失败但成功了")
             ws.close()
         except Exception as e:
@@ -162,24 +170,32 @@ def main():
     parser = argparse.ArgumentParser(description='WebSocket 测试客户端')
     parser.add_argument('--url', default='ws://localhost:7777/ws',
                         help='WebSocket 服务器地址 (默认: ws://localhost:7777/ws)')
-    parser.add_argument('--player-id', type=int, required=True,
-                        help='玩家 ID')
-    parser.add_argument('--room-id', type=int, required=True,
-                        help='房间 ID')
+    parser.add_argument('--token', required=True,
+                        help='/login 或 /guest 返回的 token')
+    parser.add_argument('--room-id',
+                        help='房间号')
+    parser.add_argument('--car-id', type=int, default=101,
+                        help='车辆 ID (默认: 101)')
+    parser.add_argument('--skin-id', type=int, default=1,
+                        help='皮肤 ID (默认: 1)')
+    parser.add_argument('--password',
+                        help='私密房间的密码')
     parser.add_argument('--test-errors', action='store_true',
                         help='测试错误情况')
     
     args = parser.parse_args()
     
     if args.test_errors:
-        test_connection_errors()
+        test_connection_errors(args.url, args.token)
         return
+    if not args.room_id:
+        parser.error('需要 --room-id')
     
     print("=" * 50)
     print("🎮 WebSocket 测试客户端")
     print("=" * 50)
     
-    client = WebSocketClient(args.url, args.player_id, args.room_id)
+    client = WebSocketClient(args.url, args.token, args.room_id, args.car_id, args.skin_id, args.password)
     
     try:
         client.connect()
@@ -0,0 +1,215 @@
+use axum::{
+    Json,
+    extract::{FromRequestParts, State},
+    response::IntoResponse,
+    response::Response,
+};
+use http::StatusCode;
+use minigame::{
+    Account, AddFriendRequest, AppState, AuthPlayer, ChangePasswordRequest, Friend, GuestLoginRequest, JwtKeys,
+    LoginRequest, Player, RegisterRequest, ResetPasswordRequest, RoomVisibility, UpgradeGuestRequest, add_friend, change_password,
+    guest_login, login, open_room, register, reset_password, upgrade_guest,
+};
+
+mod common;
+use common::{body_json, memory_config, memory_state, player};
+
+async fn try_login(state: &AppState, username: &str, password: &str) -> Response {
+    let request = LoginRequest {
+        username: username.to_string(),
+        password: password.to_string(),
+    };
+    login(State(state.clone()), Json(request)).await.into_response()
+}
+
+#[tokio::test]
+async fn token_is_issued_only_for_the_right_password() {
+    let state = memory_state();
+    let player_id = Account::register(&state.storage, "alice", "爱丽丝", "secret1").await.unwrap();
+
+    assert_eq!(try_login(&state, "alice", "wrong1").await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(try_login(&state, "nobody", "secret1").await.status(), StatusCode::UNAUTHORIZED);
+
+    let response = try_login(&state, " alice ", "secret1").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_json(response).await;
+    assert_eq!(body["player_id"], player_id);
+    let claims = state.jwt.verify(body["token"].as_str().unwrap()).unwrap();
+    assert_eq!(claims.sub, player_id);
+    assert!(!claims.guest);
+}
+
+// 按 Authorization 头解析身份，与 handler 收到的 AuthPlayer 相同
+async fn authenticate(state: &AppState, token: &str) -> Result<AuthPlayer, (StatusCode, &'static str)> {
+    let (mut parts, _) = http::Request::builder()
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(())
+        .unwrap()
+        .into_parts();
+    AuthPlayer::from_request_parts(&mut parts, state).await
+}
+
+#[tokio::test]
+async fn upgraded_guest_keeps_friends_and_room_and_the_guest_token_stops_working() {
+    let state = memory_state();
+    let friend = Account::register(&state.storage, "friend", "好友", "secret1").await.unwrap();
+
+    let request = GuestLoginRequest {
+        player_name: "游客".to_string(),
+    };
+    let response = guest_login(State(state.clone()), Json(request)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let guest_token = body_json(response).await["token"].as_str().unwrap().to_string();
+    let guest = authenticate(&state, &guest_token).await.unwrap();
+    assert!(guest.guest);
+    let guest_id = guest.player_id;
+
+    let request = AddFriendRequest { friend_id: friend };
+    let response = add_friend(State(state.clone()), guest.clone(), Json(request)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let room_id = open_room(&state, guest_id, 1, 1, 4, RoomVisibility::Public, None);
+    let handle = state.room(&room_id).unwrap();
+    handle
+        .update(move |room, _| room.players.push(Player { guest: true, ..player(guest_id) }))
+        .await
+        .unwrap();
+
+    let request = UpgradeGuestRequest {
+        username: "former_guest".to_string(),
+        password: "secret1".to_string(),
+    };
+    let response = upgrade_guest(State(state.clone()), guest, Json(request)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = body_json(response).await["token"].as_str().unwrap().to_string();
+
+    // 沿用游客 id：房间成员身份不变，好友关系写入存储
+    assert!(handle.read(|room| room.has_member(guest_id) && room.is_owner(guest_id)));
+    assert!(!state.guests.contains_key(&guest_id));
+    let friends = Friend::get_all_friends(&state.storage, &state.guests, guest_id).await.unwrap();
+    assert!(friends.friend_ids.iter().any(|f| f.player_id == friend));
+    let friends = Friend::get_all_friends(&state.storage, &state.guests, friend).await.unwrap();
+    assert!(friends.friend_ids.iter().any(|f| f.player_id == guest_id));
+
+    // 旧的游客 token 不能再以游客身份操作
+    assert_eq!(authenticate(&state, &guest_token).await.unwrap_err().0, StatusCode::UNAUTHORIZED);
+    let upgraded = authenticate(&state, &token).await.unwrap();
+    assert_eq!(upgraded.player_id, guest_id);
+    assert!(!upgraded.guest);
+}
+
+#[tokio::test]
+async fn register_validates_the_request_and_rejects_taken_usernames() {
+    let state = memory_state();
+    let request = |username: &str, password: &str| RegisterRequest {
+        username: username.to_string(),
+        player_name: "爱丽丝".to_string(),
+        password: password.to_string(),
+    };
+
+    let response = register(State(state.clone()), Json(request("  ", "secret1"))).await.into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = register(State(state.clone()), Json(request("alice", "short"))).await.into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = register(State(state.clone()), Json(request(" alice ", "secret1"))).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let player_id = body_json(response).await["player_id"].as_i64().unwrap() as i32;
+    assert_eq!(Account::get(&state.storage, player_id).await.unwrap().username, "alice");
+
+    let response = register(State(state.clone()), Json(request("alice", "secret2"))).await.into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+async fn login_token(state: &AppState, username: &str, password: &str) -> String {
+    let response = try_login(state, username, password).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    body_json(response).await["token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn changing_the_password_revokes_earlier_tokens() {
+    let state = memory_state();
+    Account::register(&state.storage, "alice", "爱丽丝", "secret1").await.unwrap();
+    let token = login_token(&state, "alice", "secret1").await;
+    let other_device = login_token(&state, "alice", "secret1").await;
+    let alice = authenticate(&state, &token).await.unwrap();
+
+    let request = |old_password: &str, new_password: &str| ChangePasswordRequest {
+        old_password: old_password.to_string(),
+        new_password: new_password.to_string(),
+    };
+    let response = change_password(State(state.clone()), alice.clone(), Json(request("secret1", "short")))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = change_password(State(state.clone()), alice.clone(), Json(request("wrong1", "secret2")))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(authenticate(&state, &other_device).await.is_ok());
+
+    let response = change_password(State(state.clone()), alice, Json(request("secret1", "secret2")))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let new_token = body_json(response).await["token"].as_str().unwrap().to_string();
+
+    assert_eq!(authenticate(&state, &token).await.unwrap_err().0, StatusCode::UNAUTHORIZED);
+    assert_eq!(authenticate(&state, &other_device).await.unwrap_err().0, StatusCode::UNAUTHORIZED);
+    assert!(authenticate(&state, &new_token).await.is_ok());
+    assert_eq!(try_login(&state, "alice", "secret1").await.status(), StatusCode::UNAUTHORIZED);
+    login_token(&state, "alice", "secret2").await;
+}
+
+#[tokio::test]
+async fn only_admins_reset_passwords_and_the_reset_locks_out_old_tokens() {
+    let state = memory_state();
+    let admin_id = Account::register(&state.storage, "admin", "管理员", "secret1").await.unwrap();
+    let victim_id = Account::register(&state.storage, "victim", "玩家", "secret1").await.unwrap();
+    state.storage.players.set_admin(admin_id, true).await.unwrap();
+    let stolen = login_token(&state, "victim", "secret1").await;
+    let victim = authenticate(&state, &stolen).await.unwrap();
+
+    let request = |player_id: i32, new_password: &str| ResetPasswordRequest {
+        player_id,
+        new_password: new_password.to_string(),
+    };
+    let response = reset_password(State(state.clone()), victim, Json(request(admin_id, "hijack1")))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    // 普通玩家无法改动管理员的密码
+    login_token(&state, "admin", "secret1").await;
+
+    let admin = authenticate(&state, &login_token(&state, "admin", "secret1").await).await.unwrap();
+    let response = reset_password(State(state.clone()), admin.clone(), Json(request(victim_id, "short")))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = reset_password(State(state.clone()), admin.clone(), Json(request(victim_id, "secret2")))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(authenticate(&state, &stolen).await.unwrap_err().0, StatusCode::UNAUTHORIZED);
+    assert_eq!(try_login(&state, "victim", "secret1").await.status(), StatusCode::UNAUTHORIZED);
+    login_token(&state, "victim", "secret2").await;
+}
+
+#[test]
+fn the_published_sample_key_is_refused() {
+    let mut config = memory_config();
+    let sample = std::env::temp_dir().join(format!("minigame-sample-{}.pem", std::process::id()));
+    std::fs::write(
+        &sample,
+        "-----BEGIN PUBLIC KEY-----\nMCowBQYDK2VwAyEAUangbK8ZdOv5aISxPJuPvKwqUK38ZOHQ+1PdqF/N6E4=\n-----END PUBLIC KEY-----\n",
+    )
+    .unwrap();
+    config.key.public_key_file = sample.display().to_string();
+    let result = JwtKeys::try_new(&config.key);
+    std::fs::remove_file(&sample).unwrap();
+    assert!(result.is_err());
+
+    assert!(JwtKeys::try_new(&memory_config().key).is_ok());
+}
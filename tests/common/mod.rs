@@ -0,0 +1,46 @@
+// 集成测试共用的辅助函数，各测试文件只用到其中一部分
+#![allow(dead_code)]
+
+use axum::response::Response;
+use minigame::{AppState, AuthPlayer, Config, Player, StorageBackend};
+use serde_json::Value;
+
+/// 仓库中的 config.yaml，存储改为内存，不需要数据库；使用 tests/keys 下仅供测试的密钥
+pub fn memory_config() -> Config {
+    let mut config = Config::load_from_file("config.yaml").unwrap();
+    config.storage = StorageBackend::Memory;
+    config.database = String::new();
+    config.key.private_key_file = "tests/keys/private.pem".to_string();
+    config.key.public_key_file = "tests/keys/public.pem".to_string();
+    config
+}
+
+pub fn memory_state() -> AppState {
+    AppState::try_new(&memory_config()).unwrap()
+}
+
+/// 以正式账号身份调用 handler
+pub fn auth(player_id: i32) -> AuthPlayer {
+    AuthPlayer {
+        player_id,
+        player_name: format!("玩家{}", player_id),
+        guest: false,
+    }
+}
+
+pub fn player(player_id: i32) -> Player {
+    Player {
+        player_id,
+        player_name: format!("玩家{}", player_id),
+        car_id: 100 + player_id,
+        rating: 1500,
+        guest: false,
+        ready: false,
+        reconnecting: false,
+    }
+}
+
+pub async fn body_json(response: Response) -> Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
@@ -0,0 +1,101 @@
+use axum::{Json, extract::State, response::IntoResponse, response::Response};
+use dashmap::DashMap;
+use http::StatusCode;
+use minigame::{
+    Account, AppState, Friend, InviteFriendRequest, RespondInviteRequest, RoomInvite, RoomVisibility, invite_friend,
+    open_room, respond_invite,
+};
+
+mod common;
+use common::{auth, body_json, memory_state, player};
+
+async fn invite(state: &AppState, from_id: i32, room_id: &str, friend_id: i32) -> Response {
+    let request = InviteFriendRequest {
+        room_id: room_id.to_string(),
+        friend_id,
+    };
+    invite_friend(State(state.clone()), auth(from_id), Json(request))
+        .await
+        .into_response()
+}
+
+async fn respond(state: &AppState, player_id: i32, invite_id: &str, accept: bool) -> Response {
+    let request = RespondInviteRequest {
+        invite_id: invite_id.to_string(),
+        accept,
+    };
+    respond_invite(State(state.clone()), auth(player_id), Json(request))
+        .await
+        .into_response()
+}
+
+#[test]
+fn expired_invites_are_purged() {
+    let invites = DashMap::new();
+    let live = RoomInvite::new("ABC234".to_string(), 1, "房主".to_string(), 2, 60);
+    let expired = RoomInvite::new("ABC234".to_string(), 1, "房主".to_string(), 3, 0);
+    assert!(!live.is_expired());
+    assert!(expired.is_expired());
+
+    invites.insert(live.invite_id.clone(), live.clone());
+    invites.insert(expired.invite_id.clone(), expired.clone());
+    RoomInvite::purge_expired(&invites);
+    assert!(invites.contains_key(&live.invite_id));
+    assert!(!invites.contains_key(&expired.invite_id));
+}
+
+#[tokio::test]
+async fn invites_are_answered_once_and_only_the_host_attaches_a_code() {
+    let state = memory_state();
+    let host = Account::register(&state.storage, "host", "房主", "secret1").await.unwrap();
+    let member = Account::register(&state.storage, "member", "成员", "secret1").await.unwrap();
+    let friend = Account::register(&state.storage, "friend", "好友", "secret1").await.unwrap();
+    let stranger = Account::register(&state.storage, "stranger", "路人", "secret1").await.unwrap();
+    Friend::add_friend(&state.storage, host, friend).await.unwrap();
+    Friend::add_friend(&state.storage, member, friend).await.unwrap();
+
+    let room_id = open_room(&state, host, 1, 1, 4, RoomVisibility::Friends, None);
+    let handle = state.room(&room_id).unwrap();
+    handle
+        .update(move |room, _| {
+            for player_id in [host, member] {
+                room.players.push(player(player_id));
+                room.board_car(player_id, player_id, 0).unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+    // 只能邀请好友
+    assert_eq!(invite(&state, host, &room_id, stranger).await.status(), StatusCode::FORBIDDEN);
+
+    // 拒绝后邀请被移除
+    let invite_id = body_json(invite(&state, host, &room_id, friend).await).await["invite_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(respond(&state, friend, &invite_id, false).await.status(), StatusCode::OK);
+    assert_eq!(respond(&state, friend, &invite_id, true).await.status(), StatusCode::BAD_REQUEST);
+
+    // 只有被邀请人可以回应；房主的邀请附带一次性邀请码
+    let invite_id = body_json(invite(&state, host, &room_id, friend).await).await["invite_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(respond(&state, stranger, &invite_id, true).await.status(), StatusCode::BAD_REQUEST);
+    let accepted = respond(&state, friend, &invite_id, true).await;
+    assert_eq!(accepted.status(), StatusCode::OK);
+    let accepted = body_json(accepted).await;
+    assert_eq!(accepted["room_id"], room_id.as_str());
+    let code = accepted["invite_code"].as_str().unwrap().to_string();
+    assert!(handle.read(|room| room.invite_codes.contains(&code)));
+
+    // 普通成员的邀请不附带邀请码，不能绕过仅好友限制
+    let invite_id = body_json(invite(&state, member, &room_id, friend).await).await["invite_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let accepted = body_json(respond(&state, friend, &invite_id, true).await).await;
+    assert!(accepted["invite_code"].is_null());
+    assert_eq!(handle.read(|room| room.invite_codes.len()), 1);
+}
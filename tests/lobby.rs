@@ -0,0 +1,112 @@
+use minigame::{AppState, Player, RoomListQuery, RoomVisibility, list_rooms, open_room};
+
+mod common;
+use common::{memory_state, player};
+
+// 创建房间并让玩家入座，第一个玩家为房主
+async fn room_with(
+    state: &AppState,
+    players: Vec<Player>,
+    weather_id: i32,
+    background_id: i32,
+    max_players: usize,
+    visibility: RoomVisibility,
+) -> String {
+    let room_id = open_room(state, players[0].player_id, weather_id, background_id, max_players, visibility, None);
+    state
+        .room(&room_id)
+        .unwrap()
+        .update(move |room, _| {
+            for player in players {
+                let player_id = player.player_id;
+                room.players.push(player);
+                room.board_car(player_id, player_id, 0).unwrap();
+            }
+        })
+        .await
+        .unwrap();
+    room_id
+}
+
+fn listed(state: &AppState, query: RoomListQuery) -> Vec<String> {
+    list_rooms(state, &query).rooms.into_iter().map(|room| room.room_id).collect()
+}
+
+#[tokio::test]
+async fn rooms_are_filtered_by_settings_player_count_and_host() {
+    let state = memory_state();
+    let host = Player {
+        player_name: "阿尔法车队".to_string(),
+        ..player(1)
+    };
+    let open = room_with(&state, vec![host, player(2)], 1, 2, 4, RoomVisibility::Public).await;
+    let full = room_with(&state, vec![player(3), player(4)], 2, 1, 2, RoomVisibility::Public).await;
+    room_with(&state, vec![player(5)], 1, 2, 4, RoomVisibility::Friends).await;
+
+    // 非公开房间不出现在大厅
+    let mut all = listed(&state, RoomListQuery::default());
+    all.sort();
+    let mut expected = vec![open.clone(), full.clone()];
+    expected.sort();
+    assert_eq!(all, expected);
+
+    let query = |update: fn(&mut RoomListQuery)| {
+        let mut query = RoomListQuery::default();
+        update(&mut query);
+        query
+    };
+    assert_eq!(listed(&state, query(|q| q.weather_id = Some(1))), vec![open.clone()]);
+    assert_eq!(listed(&state, query(|q| q.background_id = Some(1))), vec![full.clone()]);
+    assert_eq!(listed(&state, query(|q| q.max_players = Some(2))), vec![full.clone()]);
+    assert_eq!(listed(&state, query(|q| q.has_free_seats = Some(true))), vec![open.clone()]);
+    assert_eq!(listed(&state, query(|q| q.has_free_seats = Some(false))), vec![full.clone()]);
+    assert_eq!(listed(&state, query(|q| q.host_name = Some("阿尔法".to_string()))), vec![open.clone()]);
+    assert!(listed(&state, query(|q| q.host_name = Some("贝塔".to_string()))).is_empty());
+    assert_eq!(listed(&state, query(|q| q.min_player_count = Some(2))).len(), 2);
+    assert!(listed(&state, query(|q| q.min_player_count = Some(3))).is_empty());
+    assert!(listed(&state, query(|q| q.max_player_count = Some(1))).is_empty());
+
+    // 条件同时生效
+    let both = query(|q| {
+        q.weather_id = Some(1);
+        q.max_players = Some(2);
+    });
+    assert!(listed(&state, both).is_empty());
+}
+
+#[tokio::test]
+async fn pages_are_stable_and_cover_every_room_once() {
+    let state = memory_state();
+    let mut room_ids = Vec::new();
+    for player_id in 1..=5 {
+        room_ids.push(room_with(&state, vec![player(player_id)], 1, 1, 4, RoomVisibility::Public).await);
+    }
+    room_ids.sort();
+
+    let page = |page: usize, page_size: usize| {
+        list_rooms(
+            &state,
+            &RoomListQuery {
+                page: Some(page),
+                page_size: Some(page_size),
+                ..RoomListQuery::default()
+            },
+        )
+    };
+    let mut seen = Vec::new();
+    for (number, len) in [(1, 2), (2, 2), (3, 1)] {
+        let result = page(number, 2);
+        assert_eq!(result.total, 5);
+        assert_eq!(result.page, number);
+        assert_eq!(result.page_size, 2);
+        assert_eq!(result.rooms.len(), len);
+        seen.extend(result.rooms.into_iter().map(|room| room.room_id));
+    }
+    assert_eq!(seen, room_ids);
+    assert!(page(4, 2).rooms.is_empty());
+
+    // 页码从 1 开始，每页至少一个房间
+    let result = page(0, 0);
+    assert_eq!((result.page, result.page_size), (1, 1));
+    assert_eq!(result.rooms[0].room_id, room_ids[0]);
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use minigame::{AppState, MatchAssignment, MatchPreferences, MatchTicket, Player, RoomVisibility, open_room, try_match};
+use tokio::sync::oneshot;
+
+mod common;
+use common::{memory_state, player};
+
+fn preferences(weather_id: Option<i32>) -> MatchPreferences {
+    MatchPreferences {
+        car_id: 1,
+        weather_id,
+        background_id: None,
+    }
+}
+
+// 加入队列，waited 模拟已经排队的时间
+fn enqueue(
+    state: &AppState,
+    player_id: i32,
+    rating: i32,
+    weather_id: Option<i32>,
+    waited: Duration,
+) -> oneshot::Receiver<MatchAssignment> {
+    let (mut ticket, assignment_rx) = MatchTicket::new(preferences(weather_id), rating);
+    ticket.enqueued_at = Instant::now() - waited;
+    state.match_queue.insert(player_id, ticket);
+    assignment_rx
+}
+
+// 公开房间，玩家都是给定的分数
+async fn public_room(state: &AppState, player_ids: &[i32], rating: i32, weather_id: i32) -> String {
+    let room_id = open_room(state, player_ids[0], weather_id, 1, 4, RoomVisibility::Public, None);
+    let players: Vec<Player> = player_ids.iter().map(|&id| Player { rating, ..player(id) }).collect();
+    state
+        .room(&room_id)
+        .unwrap()
+        .update(move |room, _| room.players.extend(players))
+        .await
+        .unwrap();
+    room_id
+}
+
+#[tokio::test]
+async fn open_rooms_are_chosen_by_rating_gap_and_the_window_grows_while_waiting() {
+    let state = memory_state();
+    public_room(&state, &[1, 2], 1500, 1).await;
+    let strong = public_room(&state, &[3], 1700, 1).await;
+    public_room(&state, &[4], 1690, 2).await;
+
+    // 两个房间都在窗口内时选分差小的；天气不符的房间不考虑
+    let mut assignment_rx = enqueue(&state, 10, 1660, Some(1), Duration::ZERO);
+    assert!(try_match(&state, 10));
+    assert_eq!(assignment_rx.try_recv().unwrap().room_id, strong);
+    assert!(!state.match_queue.contains_key(&10));
+
+    // 分差超出初始窗口时继续排队，等待后窗口扩大
+    enqueue(&state, 11, 2000, Some(1), Duration::ZERO);
+    assert!(!try_match(&state, 11));
+    assert!(state.match_queue.contains_key(&11));
+    let mut assignment_rx = enqueue(&state, 11, 2000, Some(1), Duration::from_secs(30));
+    assert!(try_match(&state, 11));
+    assert_eq!(assignment_rx.try_recv().unwrap().room_id, strong);
+}
+
+#[tokio::test]
+async fn queued_players_with_compatible_preferences_are_grouped_into_a_new_room() {
+    let state = memory_state();
+    let mut first = enqueue(&state, 1, 1500, Some(2), Duration::from_secs(3));
+    let mut other_weather = enqueue(&state, 2, 1520, Some(3), Duration::from_secs(2));
+    let mut any_weather = enqueue(&state, 3, 1550, None, Duration::from_secs(1));
+    let mut far = enqueue(&state, 4, 2500, Some(3), Duration::ZERO);
+
+    assert!(try_match(&state, 1));
+    let assignment = first.try_recv().unwrap();
+    assert_eq!(assignment.weather_id, 2);
+    assert_eq!(any_weather.try_recv().unwrap().room_id, assignment.room_id);
+    assert!(other_weather.try_recv().is_err());
+    assert!(far.try_recv().is_err());
+    assert!(state.match_queue.contains_key(&2) && state.match_queue.contains_key(&4));
+
+    // 新房间由发起匹配的玩家作为房主，且是公开房间
+    let room = state.room(&assignment.room_id).unwrap().snapshot();
+    assert!(room.is_owner(1));
+    assert_eq!(room.visibility, RoomVisibility::Public);
+
+    // 剩下的玩家天气不兼容、分数相差太远，凑不够 min_players
+    assert!(!try_match(&state, 2));
+    assert!(!try_match(&state, 4));
+    assert_eq!(state.rooms.len(), 1);
+}
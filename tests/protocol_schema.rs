@@ -0,0 +1,19 @@
+use std::fs;
+
+// 协议文档由代码生成，修改协议后运行 `UPDATE_PROTOCOL_SCHEMA=1 cargo test` 重新生成
+const SCHEMA_PATH: &str = "docs/protocol.schema.json";
+
+#[test]
+fn protocol_schema_document_is_up_to_date() {
+    let generated = serde_json::to_string_pretty(&minigame::protocol_schema()).unwrap() + "\n";
+    if std::env::var_os("UPDATE_PROTOCOL_SCHEMA").is_some() {
+        fs::write(SCHEMA_PATH, &generated).unwrap();
+        return;
+    }
+    let committed = fs::read_to_string(SCHEMA_PATH).unwrap_or_default();
+    assert!(
+        committed == generated,
+        "{} 已过期，请运行 `UPDATE_PROTOCOL_SCHEMA=1 cargo test` 重新生成",
+        SCHEMA_PATH
+    );
+}
@@ -0,0 +1,81 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use http::StatusCode;
+use minigame::{
+    Account, DEFAULT_RATING, Guest, MatchConfig, Player, ReportResultRequest, RoomPhase, RoomVisibility, elo_deltas,
+    open_room, report_result,
+};
+use std::time::Duration;
+
+mod common;
+use common::{auth, body_json, memory_state, player};
+
+#[test]
+fn equal_ratings_move_symmetrically() {
+    let deltas = elo_deltas(&[DEFAULT_RATING, DEFAULT_RATING]);
+    assert_eq!(deltas, vec![16, -16]);
+}
+
+#[test]
+fn upset_win_moves_more_than_expected_win() {
+    let favourite_wins = elo_deltas(&[1800, 1400]);
+    let underdog_wins = elo_deltas(&[1400, 1800]);
+    assert!(underdog_wins[0] > favourite_wins[0]);
+    assert!(underdog_wins[1] < favourite_wins[1]);
+}
+
+#[test]
+fn multiplayer_deltas_follow_finishing_order() {
+    let deltas = elo_deltas(&[1500, 1500, 1500, 1500]);
+    assert!(deltas.windows(2).all(|w| w[0] > w[1]));
+    assert_eq!(deltas.iter().sum::<i32>(), 0);
+}
+
+#[test]
+fn rating_window_widens_with_wait_and_is_capped() {
+    let config = MatchConfig::default();
+    assert_eq!(config.rating_window(Duration::ZERO), config.rating_window);
+    assert!(config.rating_window(Duration::from_secs(10)) > config.rating_window);
+    assert_eq!(
+        config.rating_window(Duration::from_secs(3600)),
+        config.max_rating_window
+    );
+}
+
+#[tokio::test]
+async fn guests_in_the_room_are_not_rated_after_their_record_expires() {
+    let state = memory_state();
+    let host = Account::register(&state.storage, "host", "房主", "secret1").await.unwrap();
+    let rival = Account::register(&state.storage, "rival", "对手", "secret1").await.unwrap();
+    // 游客 token 仍然有效，但内存中的游客记录已被清理
+    let guest = Guest::allocate_id(&state.storage).await.unwrap();
+    assert!(!state.guests.contains_key(&guest));
+
+    let room_id = open_room(&state, host, 1, 1, 4, RoomVisibility::Public, None);
+    let handle = state.room(&room_id).unwrap();
+    handle
+        .update(move |room, _| {
+            for player_id in [host, rival, guest] {
+                room.players.push(Player {
+                    guest: player_id == guest,
+                    ready: true,
+                    ..player(player_id)
+                });
+            }
+            room.transition(RoomPhase::ReadyCheck).unwrap();
+            room.start_countdown().unwrap();
+            room.transition(RoomPhase::Racing).unwrap();
+        })
+        .await
+        .unwrap();
+
+    let request = ReportResultRequest {
+        room_id: room_id.clone(),
+        finishing_order: vec![guest, host, rival],
+    };
+    let response = report_result(State(state.clone()), auth(host), Json(request)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let ratings = body_json(response).await["ratings"].clone();
+    assert!(ratings.get(guest.to_string()).is_none());
+    assert!(ratings[rival.to_string()].as_i64().unwrap() < DEFAULT_RATING as i64);
+    assert_eq!(handle.read(|room| room.phase), RoomPhase::Results);
+}
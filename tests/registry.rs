@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use minigame::{ConnectionKind, ConnectionRegistry, ServerMessage};
+
+fn answered(player_id: i32) -> ServerMessage {
+    ServerMessage::InviteAnswered {
+        invite_id: "invite".to_string(),
+        player_id,
+        accepted: true,
+    }
+}
+
+#[tokio::test]
+async fn messages_fan_out_to_every_connection_of_a_player() {
+    let registry = Arc::new(ConnectionRegistry::default());
+    assert!(!registry.is_online(7));
+    assert_eq!(registry.send(7, &answered(1)), 0);
+
+    let (session, mut session_inbox) = registry.register(7, ConnectionKind::Session, None);
+    let (room, mut room_inbox) = registry.register(7, ConnectionKind::Room, Some("ABC234".to_string()));
+    let (_other, mut other_inbox) = registry.register(8, ConnectionKind::Lobby, None);
+    assert!(registry.is_online(7));
+    assert_eq!(
+        registry.connections_of(7),
+        vec![
+            (session.connection_id(), ConnectionKind::Session, None),
+            (room.connection_id(), ConnectionKind::Room, Some("ABC234".to_string())),
+        ]
+    );
+
+    assert_eq!(registry.send(7, &answered(1)), 2);
+    assert!(matches!(session_inbox.recv().await, Some(ServerMessage::InviteAnswered { player_id: 1, .. })));
+    assert!(matches!(room_inbox.recv().await, Some(ServerMessage::InviteAnswered { player_id: 1, .. })));
+    assert!(other_inbox.try_recv().is_err());
+
+    // guard 被 drop 后注销，最后一个连接注销后玩家离线
+    drop(room);
+    assert_eq!(registry.connections_of(7).len(), 1);
+    assert_eq!(registry.send(7, &answered(2)), 1);
+    drop(session);
+    assert!(!registry.is_online(7));
+    assert!(registry.connections_of(7).is_empty());
+    assert_eq!(registry.send(7, &answered(3)), 0);
+    assert!(registry.is_online(8));
+}
+
+#[tokio::test]
+async fn messages_to_a_full_connection_are_dropped() {
+    let registry = Arc::new(ConnectionRegistry::default());
+    let (_slow, mut slow_inbox) = registry.register(7, ConnectionKind::Session, None);
+    let (_fast, mut fast_inbox) = registry.register(7, ConnectionKind::Lobby, None);
+
+    // send 使用 try_send，不等待过慢的客户端
+    let mut sent = 0;
+    while registry.send(7, &answered(sent)) == 2 {
+        fast_inbox.recv().await.unwrap();
+        sent += 1;
+    }
+    assert!(sent > 0);
+    fast_inbox.recv().await.unwrap();
+
+    // 缓冲区腾出空位后恢复推送，被丢弃的消息不会补发
+    assert!(matches!(slow_inbox.recv().await, Some(ServerMessage::InviteAnswered { player_id: 0, .. })));
+    assert_eq!(registry.send(7, &answered(-1)), 2);
+    let mut received = vec![];
+    while let Ok(ServerMessage::InviteAnswered { player_id, .. }) = slow_inbox.try_recv() {
+        received.push(player_id);
+    }
+    assert_eq!(received.len() as i32, sent);
+    assert!(!received.contains(&sent));
+    assert_eq!(received.last(), Some(&-1));
+}
@@ -0,0 +1,64 @@
+use http::StatusCode;
+use minigame::{Account, Friend, RoomVisibility, check_room_access, hash_password, open_room};
+
+mod common;
+use common::memory_state;
+
+#[tokio::test]
+async fn private_rooms_need_the_password_or_an_invite_code() {
+    let state = memory_state();
+    let hash = hash_password("123456").unwrap();
+    let room_id = open_room(&state, 1, 1, 1, 4, RoomVisibility::Private, Some(hash));
+    let handle = state.room(&room_id).unwrap();
+
+    assert_eq!(check_room_access(&state, &room_id, 1, None, None).await, Ok(None));
+    assert_eq!(
+        check_room_access(&state, &room_id, 2, None, None).await,
+        Err((StatusCode::FORBIDDEN, "需要房间密码或邀请码"))
+    );
+    assert_eq!(
+        check_room_access(&state, &room_id, 2, Some("654321"), None).await,
+        Err((StatusCode::FORBIDDEN, "房间密码错误"))
+    );
+    assert_eq!(check_room_access(&state, &room_id, 2, Some("123456"), None).await, Ok(None));
+
+    // 邀请码在检查时不消耗，由加入房间时消耗
+    handle
+        .update(|room, _| room.invite_codes.insert("code".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        check_room_access(&state, &room_id, 2, None, Some("code")).await,
+        Ok(Some("code".to_string()))
+    );
+    assert!(handle.read(|room| room.invite_codes.contains("code")));
+    assert_eq!(
+        check_room_access(&state, &room_id, 2, Some("123456"), Some("other")).await,
+        Err((StatusCode::FORBIDDEN, "邀请码无效"))
+    );
+}
+
+#[tokio::test]
+async fn friends_only_rooms_admit_the_hosts_friends() {
+    let state = memory_state();
+    let host = Account::register(&state.storage, "host", "房主", "secret1").await.unwrap();
+    let friend = Account::register(&state.storage, "friend", "好友", "secret1").await.unwrap();
+    let stranger = Account::register(&state.storage, "stranger", "路人", "secret1").await.unwrap();
+    Friend::add_friend(&state.storage, host, friend).await.unwrap();
+    let room_id = open_room(&state, host, 1, 1, 4, RoomVisibility::Friends, None);
+
+    assert_eq!(check_room_access(&state, &room_id, friend, None, None).await, Ok(None));
+    assert_eq!(
+        check_room_access(&state, &room_id, stranger, None, None).await,
+        Err((StatusCode::FORBIDDEN, "仅房主好友可以加入"))
+    );
+    // 密码对仅好友房间无效
+    assert!(check_room_access(&state, &room_id, stranger, Some("123456"), None).await.is_err());
+
+    let public_id = open_room(&state, host, 1, 1, 4, RoomVisibility::Public, None);
+    assert_eq!(check_room_access(&state, &public_id, stranger, None, None).await, Ok(None));
+    assert_eq!(
+        check_room_access(&state, "NOROOM", stranger, None, None).await,
+        Err((StatusCode::BAD_REQUEST, "房间不存在"))
+    );
+}
@@ -0,0 +1,402 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{Json, extract::State, response::IntoResponse};
+use http::StatusCode;
+use minigame::{
+    ChangeRoomSettingsRequest, KickPlayerRequest, MessageType, QuitReason, Room, RoomChannel, RoomEvent, RoomHandle,
+    RoomPhase, RoomVisibility, Spectator, ban_player, change_room_settings, check_room_access, kick_player,
+};
+use tokio::sync::broadcast;
+
+mod common;
+use common::{auth, memory_state, player};
+
+fn room(player_ids: &[i32]) -> Room {
+    Room {
+        room_id: "ABC234".to_string(),
+        owner_id: player_ids[0],
+        players: player_ids.iter().copied().map(player).collect(),
+        cars: vec![],
+        weather_id: 1,
+        background_id: 1,
+        max_players: 8,
+        car_seats: 2,
+        visibility: RoomVisibility::Public,
+        password_hash: None,
+        invite_codes: HashSet::new(),
+        spectators: vec![],
+        spectator_chat: true,
+        banned_ids: HashSet::new(),
+        phase: RoomPhase::Lobby,
+        phase_epoch: 0,
+        sessions: HashMap::new(),
+        settled_epoch: None,
+        closed: false,
+    }
+}
+
+#[test]
+fn ready_toggles_between_lobby_and_ready_check() {
+    let mut room = room(&[1, 2]);
+    assert_eq!(room.set_ready(1, true), Ok(Some(RoomPhase::ReadyCheck)));
+    assert_eq!(room.set_ready(2, true), Ok(None));
+    assert_eq!(room.set_ready(1, false), Ok(None));
+    assert_eq!(room.set_ready(2, false), Ok(Some(RoomPhase::Lobby)));
+}
+
+#[test]
+fn countdown_requires_everyone_ready() {
+    let mut room = room(&[1, 2]);
+    room.set_ready(1, true).unwrap();
+    assert!(room.start_countdown().is_err());
+    room.set_ready(2, true).unwrap();
+    assert!(room.start_countdown().is_ok());
+    assert_eq!(room.phase, RoomPhase::Countdown);
+}
+
+#[test]
+fn full_cycle_returns_to_lobby_and_clears_ready() {
+    let mut room = room(&[1]);
+    room.set_ready(1, true).unwrap();
+    room.start_countdown().unwrap();
+    room.transition(RoomPhase::Racing).unwrap();
+    room.transition(RoomPhase::Results).unwrap();
+    room.transition(RoomPhase::Lobby).unwrap();
+    assert!(room.players.iter().all(|p| !p.ready));
+    assert_eq!(room.phase_epoch, 5);
+}
+
+#[test]
+fn actions_during_race_are_rejected() {
+    let mut room = room(&[1]);
+    room.set_ready(1, true).unwrap();
+    room.start_countdown().unwrap();
+    room.transition(RoomPhase::Racing).unwrap();
+    assert!(!room.phase.is_idle());
+    assert!(room.set_ready(1, false).is_err());
+    assert!(room.transition(RoomPhase::Lobby).is_err());
+}
+
+#[test]
+fn aborted_race_returns_to_lobby_without_results() {
+    let mut room = room(&[1, 2]);
+    room.set_ready(1, true).unwrap();
+    room.set_ready(2, true).unwrap();
+    room.start_countdown().unwrap();
+    assert!(room.abort_race().is_err());
+
+    room.transition(RoomPhase::Racing).unwrap();
+    let epoch = room.phase_epoch;
+    room.abort_race().unwrap();
+    assert_eq!(room.phase, RoomPhase::Lobby);
+    assert_eq!(room.phase_epoch, epoch + 1);
+    assert!(room.players.iter().all(|p| !p.ready));
+    assert!(room.abort_race().is_err());
+}
+
+#[test]
+fn each_race_accepts_one_result() {
+    let mut room = room(&[1, 2]);
+    assert!(room.claim_result().is_err());
+    room.set_ready(1, true).unwrap();
+    room.set_ready(2, true).unwrap();
+    room.start_countdown().unwrap();
+    room.transition(RoomPhase::Racing).unwrap();
+
+    let epoch = room.claim_result().unwrap();
+    assert_eq!(room.claim_result(), Err("本局成绩已提交"));
+    // 结算失败后可以重新提交
+    room.release_result(epoch);
+    assert_eq!(room.claim_result(), Ok(epoch));
+
+    // 下一局重新开始计算
+    room.transition(RoomPhase::Results).unwrap();
+    room.transition(RoomPhase::Lobby).unwrap();
+    room.set_ready(1, true).unwrap();
+    room.set_ready(2, true).unwrap();
+    room.start_countdown().unwrap();
+    room.transition(RoomPhase::Racing).unwrap();
+    assert!(room.claim_result().is_ok());
+}
+
+#[test]
+fn spectator_takes_a_free_seat_only_between_races() {
+    let mut room = room(&[1]);
+    room.max_players = 2;
+    room.spectators.push(Spectator {
+        player_id: 9,
+        player_name: "观战者".to_string(),
+    });
+    room.set_ready(1, true).unwrap();
+    room.start_countdown().unwrap();
+    assert!(room.promote_spectator(player(9), 1).is_err());
+
+    room.transition(RoomPhase::Racing).unwrap();
+    room.transition(RoomPhase::Results).unwrap();
+    room.transition(RoomPhase::Lobby).unwrap();
+    assert!(room.promote_spectator(player(9), 1).is_ok());
+    assert!(!room.is_spectator(9));
+    assert_eq!(room.players.len(), 2);
+    assert_eq!(room.cars.len(), 1);
+}
+
+#[test]
+fn spectator_cannot_take_a_seat_in_a_full_room() {
+    let mut room = room(&[1, 2]);
+    room.max_players = 2;
+    room.spectators.push(Spectator {
+        player_id: 9,
+        player_name: "观战者".to_string(),
+    });
+    assert_eq!(room.promote_spectator(player(9), 1), Err("房间已满"));
+    assert!(room.is_spectator(9));
+}
+
+#[test]
+fn resume_token_is_rotated_and_released_with_the_seat() {
+    let mut room = room(&[1, 2]);
+    let first = room.attach_session(1, 10);
+    assert!(room.can_resume(1, &first));
+    assert!(room.is_current_connection(1, 10));
+
+    // 重连后旧凭证失效，旧连接不再持有座位
+    let second = room.attach_session(1, 11);
+    assert!(!room.can_resume(1, &first));
+    assert!(room.can_resume(1, &second));
+    assert!(!room.is_current_connection(1, 10));
+
+    assert!(room.set_reconnecting(1, true));
+    assert!(room.players[0].reconnecting);
+    room.remove_player(1);
+    assert!(!room.can_resume(1, &second));
+    assert!(!room.set_reconnecting(1, false));
+}
+
+#[test]
+fn leaving_hands_over_the_host_and_closes_the_empty_room() {
+    let mut room = room(&[1, 2]);
+    room.spectators.push(Spectator {
+        player_id: 3,
+        player_name: "观战者".to_string(),
+    });
+    room.set_ready(2, true).unwrap();
+
+    let departure = room.leave(2).unwrap();
+    assert_eq!(departure.new_owner, None);
+    assert!(departure.phase_changed);
+    assert_eq!(room.phase, RoomPhase::Lobby);
+
+    // 观战者离开不会关闭房间
+    assert!(!room.leave(3).unwrap().closed);
+    assert!(room.leave(3).is_err());
+
+    let departure = room.leave(1).unwrap();
+    assert!(departure.was_player && departure.closed);
+    assert!(room.closed);
+}
+
+#[tokio::test]
+async fn room_task_applies_commands_in_order_and_stops_when_closed() {
+    let handle = RoomHandle::spawn(room(&[1]), RoomChannel::new(16));
+    let mut rx = handle.channel().subscribe();
+
+    let phase = handle
+        .update(|room, channel| {
+            let phase = room.set_ready(1, true).unwrap();
+            channel.send(MessageType::Sync(Box::new(room.clone()))).unwrap();
+            phase
+        })
+        .await;
+    assert_eq!(phase, Some(Some(RoomPhase::ReadyCheck)));
+    // 收到结果时快照已经包含这次修改
+    assert_eq!(handle.read(|room| room.phase), RoomPhase::ReadyCheck);
+    assert_eq!(rx.recv().await.unwrap().seq, 1);
+
+    let closed = handle.update(|room, _| room.leave(1).unwrap().closed).await;
+    assert_eq!(closed, Some(true));
+    assert!(handle.update(|room, _| room.players.len()).await.is_none());
+}
+
+#[test]
+fn cars_share_seats_without_duplicate_ids() {
+    let mut room = room(&[1, 2, 3]);
+    room.board_car(1, 7, 1).unwrap();
+    room.board_car(2, 7, 5).unwrap();
+    assert_eq!(room.board_car(3, 7, 1), Err("车辆已满"));
+    assert_eq!(room.cars.len(), 1);
+    assert_eq!(room.cars[0].player_ids, vec![1, 2]);
+
+    // 换车后原车辆空出座位，玩家记录的 car_id 随之更新
+    room.board_car(2, 8, 5).unwrap();
+    assert!(room.has_car_seat(7, 3));
+    assert_eq!(room.players[1].car_id, 8);
+
+    // 玩家离开只移除自己，不影响同车的其他玩家
+    room.board_car(3, 8, 1).unwrap();
+    room.remove_player(2);
+    assert_eq!(room.cars.iter().find(|car| car.car_id == 8).unwrap().player_ids, vec![3]);
+    room.remove_player(1);
+    assert!(room.cars.iter().all(|car| car.car_id != 7));
+}
+
+fn host_action(room_id: &str, player_id: i32) -> Json<KickPlayerRequest> {
+    Json(KickPlayerRequest {
+        room_id: room_id.to_string(),
+        player_id,
+    })
+}
+
+// 跳过同步等消息，返回下一条 Quit 的玩家和原因
+async fn next_quit(rx: &mut broadcast::Receiver<RoomEvent>) -> (i32, Option<QuitReason>) {
+    loop {
+        if let MessageType::Quit(player_id, _, reason) = rx.recv().await.unwrap().message {
+            return (player_id, reason);
+        }
+    }
+}
+
+#[tokio::test]
+async fn only_the_host_kicks_and_bans_and_banned_players_cannot_rejoin() {
+    let state = memory_state();
+    let handle = RoomHandle::spawn(room(&[1, 2, 3]), RoomChannel::new(16));
+    let room_id = handle.room_id().to_string();
+    state.rooms.insert(room_id.clone(), handle.clone());
+    let mut rx = handle.channel().subscribe();
+
+    // 普通玩家不能踢人或封禁
+    let response = kick_player(State(state.clone()), auth(2), host_action(&room_id, 3)).await.into_response();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = ban_player(State(state.clone()), auth(2), host_action(&room_id, 3)).await.into_response();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(handle.read(|room| room.players.len()), 3);
+    assert!(handle.read(|room| room.banned_ids.is_empty()));
+
+    // 被踢出的连接以 4001 关闭，之后仍可以重新加入
+    let response = kick_player(State(state.clone()), auth(1), host_action(&room_id, 3)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(next_quit(&mut rx).await, (3, Some(QuitReason::Kicked)));
+    assert_eq!(QuitReason::Kicked.close_code(), 4001);
+    assert!(check_room_access(&state, &room_id, 3, None, None).await.is_ok());
+
+    // 被封禁的连接以 4003 关闭，房间存在期间不能再加入
+    let response = ban_player(State(state.clone()), auth(1), host_action(&room_id, 2)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(next_quit(&mut rx).await, (2, Some(QuitReason::Banned)));
+    assert_eq!(QuitReason::Banned.close_code(), 4003);
+    assert!(!handle.read(|room| room.has_member(2)));
+    assert_eq!(
+        check_room_access(&state, &room_id, 2, None, None).await,
+        Err((StatusCode::FORBIDDEN, "已被房主禁止加入该房间"))
+    );
+
+    // 可以提前封禁不在房间中的玩家
+    let response = ban_player(State(state.clone()), auth(1), host_action(&room_id, 9)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(check_room_access(&state, &room_id, 9, None, None).await.is_err());
+}
+
+#[test]
+fn first_seated_player_takes_over_an_absent_host() {
+    // 匹配或创建房间的玩家一直没有连接
+    let mut room = room(&[1]);
+    room.players.clear();
+    room.spectators.push(Spectator {
+        player_id: 2,
+        player_name: "观战者".to_string(),
+    });
+    // 观战者不接任房主
+    assert!(!room.claim_vacant_host(2));
+    assert!(!room.claim_vacant_host(3));
+
+    room.players.push(player(3));
+    assert!(room.claim_vacant_host(3));
+    assert!(room.is_owner(3));
+
+    // 房主在座位上时不再变更
+    room.promote_spectator(player(2), 1).unwrap();
+    assert!(!room.claim_vacant_host(2));
+    assert!(room.is_owner(3));
+}
+
+#[tokio::test]
+async fn spectators_can_be_kicked_and_banned() {
+    let state = memory_state();
+    let mut spectated = room(&[1]);
+    for player_id in [5, 6] {
+        spectated.spectators.push(Spectator {
+            player_id,
+            player_name: format!("观战者{}", player_id),
+        });
+    }
+    let handle = RoomHandle::spawn(spectated, RoomChannel::new(16));
+    let room_id = handle.room_id().to_string();
+    state.rooms.insert(room_id.clone(), handle.clone());
+    let mut rx = handle.channel().subscribe();
+
+    let response = kick_player(State(state.clone()), auth(1), host_action(&room_id, 5)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(next_quit(&mut rx).await, (5, Some(QuitReason::Kicked)));
+    assert!(!handle.read(|room| room.is_spectator(5)));
+
+    let response = ban_player(State(state.clone()), auth(1), host_action(&room_id, 6)).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(next_quit(&mut rx).await, (6, Some(QuitReason::Banned)));
+    assert!(!handle.read(|room| room.is_spectator(6)));
+    assert!(check_room_access(&state, &room_id, 6, None, None).await.is_err());
+}
+
+fn settings(room_id: &str, weather_id: Option<i32>, background_id: Option<i32>) -> Json<ChangeRoomSettingsRequest> {
+    Json(ChangeRoomSettingsRequest {
+        room_id: room_id.to_string(),
+        weather_id,
+        background_id,
+        spectator_chat: None,
+    })
+}
+
+#[tokio::test]
+async fn only_the_host_changes_settings_and_only_to_catalog_entries() {
+    let state = memory_state();
+    let handle = RoomHandle::spawn(room(&[1, 2]), RoomChannel::new(16));
+    let room_id = handle.room_id().to_string();
+    state.rooms.insert(room_id.clone(), handle.clone());
+    let mut rx = handle.channel().subscribe();
+    let current = |handle: &RoomHandle| handle.read(|room| (room.weather_id, room.background_id, room.spectator_chat));
+
+    let response = change_room_settings(State(state.clone()), auth(2), settings(&room_id, Some(2), None))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // config.yaml 的目录中只有天气 / 背景 1~3
+    let response = change_room_settings(State(state.clone()), auth(1), settings(&room_id, Some(9), None))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = change_room_settings(State(state.clone()), auth(1), settings(&room_id, None, Some(9)))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(current(&handle), (1, 1, true));
+
+    // 省略的字段保持不变，修改后同步给房间
+    let request = Json(ChangeRoomSettingsRequest {
+        spectator_chat: Some(false),
+        ..settings(&room_id, Some(2), None).0
+    });
+    let response = change_room_settings(State(state.clone()), auth(1), request).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(current(&handle), (2, 1, false));
+    let MessageType::Sync(synced) = rx.recv().await.unwrap().message else {
+        panic!("修改设置后应当同步房间");
+    };
+    assert_eq!((synced.weather_id, synced.spectator_chat), (2, false));
+
+    // 比赛中不能修改
+    handle.update(|room, _| room.phase = RoomPhase::Racing).await.unwrap();
+    let response = change_room_settings(State(state.clone()), auth(1), settings(&room_id, None, Some(3)))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(current(&handle), (2, 1, false));
+}
@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+
+use minigame::{Account, AppState, DEFAULT_RATING, Friend, Guest, Rating, Storage, StorageBackend};
+
+mod common;
+use common::memory_config;
+
+#[tokio::test]
+async fn accounts_are_registered_and_looked_up_in_memory() {
+    let storage = Storage::memory();
+    let player_id = Account::register(&storage, "alice", "爱丽丝", "secret1").await.unwrap();
+    assert!(Account::register(&storage, "alice", "重名", "secret2").await.is_err());
+
+    let account = Account::get_by_username(&storage, "alice").await.unwrap();
+    assert_eq!(account.player_id, player_id);
+    assert!(account.verify_password("secret1"));
+
+    Account::set_password(&storage, player_id, "secret3").await.unwrap();
+    assert!(Account::get(&storage, player_id).await.unwrap().verify_password("secret3"));
+    assert!(Account::set_password(&storage, player_id + 100, "secret3").await.is_err());
+
+    // 游客 id 与正式账号来自同一个序列，升级时沿用
+    let guest_id = Guest::allocate_id(&storage).await.unwrap();
+    assert_ne!(guest_id, player_id);
+    Account::register_with_id(&storage, guest_id, "bob", "鲍勃", "secret4").await.unwrap();
+    assert_eq!(Account::get_by_username(&storage, "bob").await.unwrap().player_id, guest_id);
+}
+
+#[tokio::test]
+async fn friendships_are_stored_in_both_directions() {
+    let storage = Storage::memory();
+    let alice = Account::register(&storage, "alice", "爱丽丝", "secret1").await.unwrap();
+    let bob = Account::register(&storage, "bob", "鲍勃", "secret2").await.unwrap();
+    assert!(Friend::add_friend(&storage, alice, 999).await.is_err());
+
+    Friend::add_friend(&storage, alice, bob).await.unwrap();
+    assert!(Friend::add_friend(&storage, bob, alice).await.is_err());
+    let friends = Friend::get_friends(&storage, bob).await.unwrap();
+    assert_eq!(friends.friend_ids.len(), 1);
+    assert_eq!(friends.friend_ids[0].player_id, alice);
+
+    Friend::remove_friend(&storage, alice, bob).await.unwrap();
+    assert!(Friend::remove_friend(&storage, alice, bob).await.is_err());
+    assert!(Friend::get_friends(&storage, alice).await.unwrap().friend_ids.is_empty());
+}
+
+#[tokio::test]
+async fn finishing_order_updates_ratings_but_not_guests() {
+    let storage = Storage::memory();
+    assert_eq!(Rating::get(&storage, 1).await.unwrap(), DEFAULT_RATING);
+
+    let guests = HashSet::from([3]);
+    let updated = Rating::apply_finishing_order(&storage, &[1, 2, 3], &guests).await.unwrap();
+    assert_eq!(updated.len(), 2);
+    assert!(updated[&1] > DEFAULT_RATING && updated[&2] < updated[&1]);
+    assert_eq!(Rating::get(&storage, 1).await.unwrap(), updated[&1]);
+    assert_eq!(Rating::get(&storage, 3).await.unwrap(), DEFAULT_RATING);
+}
+
+#[tokio::test]
+async fn server_state_starts_without_a_database() {
+    let mut config = memory_config();
+    let state = AppState::try_new(&config).unwrap();
+    assert!(Account::register(&state.storage, "carol", "卡罗尔", "secret1").await.is_ok());
+
+    // Postgres 连接串无效时返回错误而不是 panic
+    config.storage = StorageBackend::Postgres;
+    assert!(AppState::try_new(&config).is_err());
+}
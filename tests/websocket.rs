@@ -0,0 +1,187 @@
+use minigame::{
+    ErrorCode, FrameError, MessageResponse, MessageType, PROTOCOL_VERSION, ServerMessage,
+    negotiate_protocol_version, parse_client_message,
+};
+
+#[test]
+fn text_frame_is_stamped_with_connection_identity() {
+    let message = parse_client_message(r#"{"type":"text","content":"你好"}"#, 7).unwrap();
+    match message {
+        MessageType::Text(MessageResponse { player_id, content }) => {
+            assert_eq!(player_id, 7);
+            assert_eq!(content, "你好");
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+}
+
+#[test]
+fn emoji_frame_with_matching_player_id_is_accepted() {
+    let message =
+        parse_client_message(r#"{"type":"emoji","player_id":7,"content":"smile"}"#, 7).unwrap();
+    match message {
+        MessageType::Emoji(MessageResponse { player_id, content }) => {
+            assert_eq!(player_id, 7);
+            assert_eq!(content, "smile");
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+}
+
+#[test]
+fn frame_claiming_another_player_is_rejected() {
+    for kind in ["text", "emoji"] {
+        let frame = format!(r#"{{"type":"{}","player_id":8,"content":"hi"}}"#, kind);
+        let err = parse_client_message(&frame, 7).unwrap_err();
+        assert_eq!(err, FrameError::SenderMismatch(8));
+        match err.to_server_message() {
+            ServerMessage::Error { code, .. } => assert_eq!(code, ErrorCode::SenderMismatch),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn malformed_frames_are_rejected() {
+    for frame in [
+        "not json",
+        r#"{"type":"text"}"#,
+        r#"{"type":"text","player_id":"7","content":"hi"}"#,
+        r#"{"type":"voice","content":"hi"}"#,
+        r#"{"mes_type":"text","content":"hi"}"#,
+    ] {
+        let err = parse_client_message(frame, 7).unwrap_err();
+        assert!(matches!(err, FrameError::Malformed(_)), "{}", frame);
+        let json = serde_json::to_value(err.to_server_message()).unwrap();
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["code"], "malformed_frame");
+    }
+}
+
+#[test]
+fn protocol_version_negotiation() {
+    assert_eq!(negotiate_protocol_version(None), Some(PROTOCOL_VERSION));
+    assert_eq!(negotiate_protocol_version(Some(PROTOCOL_VERSION)), Some(PROTOCOL_VERSION));
+    assert_eq!(negotiate_protocol_version(Some(PROTOCOL_VERSION + 1)), Some(PROTOCOL_VERSION));
+    assert_eq!(negotiate_protocol_version(Some(0)), None);
+}
+
+#[test]
+fn wire_format_is_selected_from_subprotocol() {
+    use http::HeaderValue;
+    use minigame::{MSGPACK_SUBPROTOCOL, WireFormat};
+
+    assert_eq!(WireFormat::from_subprotocol(None), WireFormat::Json);
+    let msgpack = HeaderValue::from_static(MSGPACK_SUBPROTOCOL);
+    assert_eq!(WireFormat::from_subprotocol(Some(&msgpack)), WireFormat::MessagePack);
+}
+
+#[test]
+fn msgpack_frames_round_trip() {
+    use axum::extract::ws::Message;
+    use minigame::{ClientMessage, WireFormat};
+
+    let client = ClientMessage::Emoji {
+        content: "smile".to_string(),
+        player_id: None,
+    };
+    let frame = Message::Binary(rmp_serde::to_vec_named(&client).unwrap().into());
+    assert_eq!(WireFormat::MessagePack.decode(&frame).unwrap(), client);
+    // 二进制连接上的文本帧会被拒绝
+    let text = Message::Text(r#"{"type":"emoji","content":"smile"}"#.into());
+    assert!(WireFormat::MessagePack.decode(&text).is_err());
+
+    let server = ServerMessage::Text {
+        seq: 1,
+        player_id: 7,
+        content: "hi".to_string(),
+    };
+    match WireFormat::MessagePack.encode(&server) {
+        Message::Binary(bytes) => {
+            let value: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+            assert_eq!(value["type"], "text");
+            assert_eq!(value["player_id"], 7);
+        }
+        other => panic!("unexpected frame: {:?}", other),
+    }
+}
+
+#[test]
+fn room_channel_numbers_messages_and_replays_history() {
+    use minigame::RoomChannel;
+
+    let text = |content: &str| {
+        MessageType::Text(MessageResponse {
+            player_id: 7,
+            content: content.to_string(),
+        })
+    };
+    let channel = RoomChannel::new(3);
+    let mut rx = channel.subscribe();
+    assert_eq!(channel.send(text("a")).unwrap(), 1);
+    // Quit 只在服务器内部使用，不占用序号
+    assert_eq!(channel.send(MessageType::Quit(7, "ABC234".to_string(), None)).unwrap(), 1);
+    assert_eq!(channel.send(text("b")).unwrap(), 2);
+    assert_eq!(rx.try_recv().unwrap().seq, 1);
+
+    let replayed = channel.replay_after(1).unwrap();
+    assert_eq!(replayed.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2]);
+    assert!(channel.replay_after(2).unwrap().is_empty());
+    // 客户端声称收到了还没发出的序号
+    assert!(channel.replay_after(3).is_none());
+
+    for content in ["c", "d", "e"] {
+        channel.send(text(content)).unwrap();
+    }
+    // 历史只保留最近 3 条（3..=5），缺口过大时需要改发 sync
+    assert!(channel.replay_after(1).is_none());
+    assert_eq!(channel.replay_after(2).unwrap().len(), 3);
+    let server = ServerMessage::from_broadcast(&channel.replay_after(4).unwrap()[0]).unwrap();
+    assert_eq!(server.seq(), Some(5));
+}
+
+#[test]
+fn replay_frame_is_not_broadcast() {
+    let err = parse_client_message(r#"{"type":"replay","after_seq":3}"#, 7).unwrap_err();
+    assert!(matches!(err, FrameError::Malformed(_)));
+}
+
+#[tokio::test]
+async fn replaced_connection_is_asked_to_close() {
+    use std::sync::Arc;
+
+    use minigame::{ConnectionKind, ConnectionRegistry, QuitReason};
+
+    let registry = Arc::new(ConnectionRegistry::default());
+    let (mut old, _old_inbox) = registry.register(7, ConnectionKind::Room, Some("ABC234".to_string()));
+    let (new, _new_inbox) = registry.register(7, ConnectionKind::Room, Some("ABC234".to_string()));
+    assert!(registry.close(7, old.connection_id(), QuitReason::Replaced));
+    assert_eq!(old.closed().await, QuitReason::Replaced);
+    assert_eq!(QuitReason::Replaced.close_code(), 4002);
+
+    drop(old);
+    assert!(!registry.close(7, 0, QuitReason::Replaced));
+    assert_eq!(registry.connections_of(7).len(), 1);
+    assert_eq!(registry.connections_of(7)[0].0, new.connection_id());
+}
+
+#[test]
+fn resume_replays_only_chat_covered_by_the_welcome() {
+    use minigame::{RoomChannel, missed_chat};
+
+    let channel = RoomChannel::new(16);
+    let chat = MessageResponse {
+        player_id: 7,
+        content: "你好".to_string(),
+    };
+    channel.send(MessageType::Text(chat.clone())).unwrap();
+    channel.send(MessageType::HostChanged("ABC234".to_string(), 8)).unwrap();
+    channel.send(MessageType::Emoji(chat.clone())).unwrap();
+    // Welcome 之后的消息由广播任务下发
+    channel.send(MessageType::Text(chat)).unwrap();
+
+    let missed = missed_chat(&channel.replay_after(0).unwrap(), 3);
+    assert_eq!(missed.iter().map(|m| m.seq()).collect::<Vec<_>>(), vec![Some(1), Some(3)]);
+    assert!(matches!(missed[0], ServerMessage::Text { .. }));
+    assert!(matches!(missed[1], ServerMessage::Emoji { .. }));
+}